@@ -1,18 +1,253 @@
-use std::path::Path;
+use std::{path::Path, collections::HashMap, net::IpAddr};
 
+use ipnet::IpNet;
 use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Configuration {
     pub downstreams: Vec<DownstreamConfig>,
+    /// Names of the downstreams (or groups) to try, in order, if the default one can't be reached
+    #[serde(default, rename = "try")]
+    pub try_downstreams: Vec<String>,
+    /// Downstreams (or groups) players are moved to when theirs kicks them or goes down,
+    /// the try list is used if not set
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    /// Virtual host the player connected with -> downstream or group name. `*` can be used as a wildcard
+    #[serde(default)]
+    pub forced_hosts: HashMap<String, String>,
+    /// Load balancing settings of the downstream groups, round-robin if not set
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
     pub bind_address: String,
+    #[serde(default)]
+    pub proxy_protocol: ProxyProtocolConfig,
+    /// Encrypt connections and authenticate players with Mojang
+    #[serde(default)]
+    pub online_mode: bool,
+    /// Base URL of the session server used to authenticate players in online-mode
+    #[serde(default = "default_session_server")]
+    pub session_server: String,
+    /// Secret shared with the downstreams using Velocity modern forwarding
+    #[serde(default)]
+    pub forwarding_secret: Option<String>,
+    #[serde(default)]
+    pub motd: MotdConfig,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    /// Seconds a downstream has to accept a connection before the next one is tried
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default)]
+    pub messages: MessagesConfig,
+    /// Packets from clients and downstreams claiming to be bigger than this (in bytes) close the connection
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+}
+
+fn default_session_server() -> String {
+    "https://sessionserver.mojang.com".to_string()
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    5
+}
+
+fn default_max_frame_size() -> usize {
+    protocol::codec::DEFAULT_MAX_FRAME_SIZE
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownstreamConfig {
     pub address: String,
     pub name: String,
-    pub default: bool
+    pub default: bool,
+    /// Status for players pinging a forced host of this downstream, the global one if not set
+    #[serde(default)]
+    pub motd: Option<MotdConfig>,
+    /// Group of downstreams players get balanced between, e.g. lobbies
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Relative share of players for the weighted strategies
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// How the player's address, UUID and skin are passed on to the downstream
+    #[serde(default)]
+    pub forwarding: ForwardingMode,
+    /// Send a PROXY protocol v2 header with the player's address before anything else
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// Sent along with bungeeguard forwarding, so the downstream can tell the proxy from anyone connecting directly
+    #[serde(default)]
+    pub bungeeguard_token: Option<String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardingMode {
+    /// Nothing is forwarded, the downstream sees the proxy's address and offline UUIDs
+    None,
+    /// BungeeCord style, packed into the server address of the handshake
+    #[default]
+    Legacy,
+    /// Legacy with the BungeeGuard token of the downstream
+    Bungeeguard,
+    /// Velocity style, signed with the forwarding secret
+    Modern,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GroupConfig {
+    #[serde(default)]
+    pub strategy: BalancingStrategy,
+    /// What consistent hashing keeps players sticky by
+    #[serde(default)]
+    pub hash_key: HashKey,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalancingStrategy {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+    WeightedRandom,
+    ConsistentHash,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashKey {
+    Username,
+    #[default]
+    Uuid,
+}
+
+/// Where players can be sent: a single downstream or a group of them
+#[derive(Debug, Clone)]
+pub enum Target<'a> {
+    Downstream(&'a DownstreamConfig),
+    Group(&'a str, Vec<&'a DownstreamConfig>),
+}
+
+impl<'a> Target<'a> {
+    pub fn name(&self) -> &str {
+        match self {
+            Target::Downstream(downstream) => &downstream.name,
+            Target::Group(name, _) => name,
+        }
+    }
+
+    /// The target without the given downstream, `None` if nothing is left of it.
+    pub fn without(self, downstream: &str) -> Option<Target<'a>> {
+        match self {
+            Target::Downstream(target) if target.name == downstream => None,
+            Target::Group(name, members) => {
+                let members: Vec<_> = members.into_iter()
+                    .filter(|member| member.name != downstream)
+                    .collect();
+
+                (!members.is_empty()).then_some(Target::Group(name, members))
+            },
+            target => Some(target),
+        }
+    }
+}
+
+/// What the proxy answers to server list pings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotdConfig {
+    pub description: String,
+    pub max_players: i32,
+    pub version_name: String,
+    /// Path to a 64x64 PNG image
+    pub favicon: Option<String>,
+}
+
+impl Default for MotdConfig {
+    fn default() -> Self {
+        Self {
+            description: "A motion proxy".to_string(),
+            max_players: 100,
+            version_name: "motion".to_string(),
+            favicon: None,
+        }
+    }
+}
+
+/// Messages players get to see, `{server}` is replaced with the downstream name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessagesConfig {
+    pub server_unavailable: String,
+    /// Shown after being moved to a fallback downstream, followed by the kick reason
+    pub moved_to_fallback: String,
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            server_unavailable: "Server {server} is unavailable, please try again later.".to_string(),
+            moved_to_fallback: "You were moved to {server}: ".to_string(),
+        }
+    }
+}
+
+impl MessagesConfig {
+    pub fn server_unavailable(&self, server: &str) -> String {
+        self.server_unavailable.replace("{server}", server)
+    }
+
+    pub fn moved_to_fallback(&self, server: &str) -> String {
+        self.moved_to_fallback.replace("{server}", server)
+    }
+}
+
+/// PROXY protocol headers sent by a load balancer (HAProxy, TCPShield) in front of the proxy
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyProtocolConfig {
+    pub enabled: bool,
+    /// Networks allowed to send the header, anyone if empty.
+    /// Connections from other addresses are taken as is
+    pub trusted: Vec<IpNet>,
+}
+
+impl ProxyProtocolConfig {
+    /// Whether a connection from the address has to start with a PROXY protocol header.
+    pub fn expects_header(&self, address: IpAddr) -> bool {
+        self.enabled && (self.trusted.is_empty() || self.trusted.iter().any(|net| net.contains(&address)))
+    }
+}
+
+/// Status pings the proxy sends to find out which downstreams are up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// Successful checks in a row before a down downstream is considered up again
+    pub rise: u32,
+    /// Failed checks in a row before a downstream is considered down
+    pub fall: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 10,
+            timeout_secs: 3,
+            rise: 2,
+            fall: 3,
+        }
+    }
 }
 
 impl Configuration {
@@ -20,7 +255,161 @@ impl Configuration {
         let config: Configuration = serde_yaml::from_reader(
             std::fs::File::open(path)?
         )?;
+        config.validate()?;
 
         Ok(config)
     }
-}
\ No newline at end of file
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.downstreams.iter().filter(|downstream| downstream.default).count() > 1 {
+            return Err(anyhow::anyhow!("Only one downstream can be marked as default"));
+        }
+
+        if self.health_check.interval_secs == 0 {
+            return Err(anyhow::anyhow!("Health check interval has to be at least a second"));
+        }
+
+        for downstream in &self.downstreams {
+            match downstream.forwarding {
+                ForwardingMode::Bungeeguard if downstream.bungeeguard_token.is_none() => {
+                    return Err(anyhow::anyhow!("Downstream '{}' uses bungeeguard forwarding but has no bungeeguard_token", downstream.name));
+                },
+                ForwardingMode::Modern if self.forwarding_secret.is_none() => {
+                    return Err(anyhow::anyhow!("Downstream '{}' uses modern forwarding but no forwarding_secret is set", downstream.name));
+                },
+                _ => {},
+            }
+        }
+
+        for group in self.downstreams.iter().filter_map(|downstream| downstream.group.as_ref()) {
+            if self.downstream(group).is_some() {
+                return Err(anyhow::anyhow!("Group '{}' has the same name as a downstream", group));
+            }
+        }
+
+        for name in self.groups.keys() {
+            if self.group_members(name).is_empty() {
+                return Err(anyhow::anyhow!("Group '{}' has no downstreams", name));
+            }
+        }
+
+        for name in &self.try_downstreams {
+            if self.target(name).is_none() {
+                return Err(anyhow::anyhow!("Unknown downstream or group '{}' in the try list", name));
+            }
+        }
+
+        for name in &self.fallback {
+            if self.target(name).is_none() {
+                return Err(anyhow::anyhow!("Unknown downstream or group '{}' in the fallback list", name));
+            }
+        }
+
+        for (host, name) in &self.forced_hosts {
+            if self.target(name).is_none() {
+                return Err(anyhow::anyhow!("Unknown downstream or group '{}' for forced host '{}'", name, host));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn downstream(&self, name: &str) -> Option<&DownstreamConfig> {
+        self.downstreams.iter().find(|downstream| downstream.name == name)
+    }
+
+    pub fn group_members(&self, group: &str) -> Vec<&DownstreamConfig> {
+        self.downstreams.iter()
+            .filter(|downstream| downstream.group.as_deref() == Some(group))
+            .collect()
+    }
+
+    /// Downstream or group with the given name, downstreams take precedence.
+    pub fn target(&self, name: &str) -> Option<Target<'_>> {
+        if let Some(downstream) = self.downstream(name) {
+            return Some(Target::Downstream(downstream));
+        }
+
+        let members = self.group_members(name);
+        let group = members.first()?.group.as_deref()?;
+
+        Some(Target::Group(group, members))
+    }
+
+    /// Downstream or group forced for the (normalized) virtual host.
+    /// Exact matches win over wildcards, longer wildcard patterns win over shorter ones.
+    pub fn forced_target(&self, host: &str) -> Option<Target<'_>> {
+        let exact = self.forced_hosts.iter().find(|(pattern, _)| pattern.eq_ignore_ascii_case(host));
+
+        let name = match exact {
+            Some((_, name)) => name,
+            None => self.forced_hosts.iter()
+                .filter(|(pattern, _)| pattern.contains('*') && wildcard_match(pattern, host))
+                .max_by_key(|(pattern, _)| pattern.len())
+                .map(|(_, name)| name)?,
+        };
+
+        self.target(name)
+    }
+
+    /// Where a freshly connected player is sent to, in the order it should be tried:
+    /// the forced target for the host first, then the default downstream and the try list.
+    pub fn initial_targets(&self, host: &str) -> Vec<Target<'_>> {
+        let mut targets: Vec<Target> = vec![];
+
+        let forced = self.forced_target(host);
+        let default = self.downstreams.iter().find(|downstream| downstream.default).map(Target::Downstream);
+        let tries = self.try_downstreams.iter().filter_map(|name| self.target(name));
+
+        for target in forced.into_iter().chain(default).chain(tries) {
+            if !targets.iter().any(|added| added.name() == target.name()) {
+                targets.push(target);
+            }
+        }
+
+        // Nothing configured, fall back to the first one
+        if targets.is_empty() {
+            targets.extend(self.downstreams.first().map(Target::Downstream));
+        }
+
+        targets
+    }
+
+    /// Where a player is moved to when the downstream they are playing on goes away,
+    /// in the order it should be tried. The lost downstream itself is left out.
+    pub fn fallback_targets(&self, lost: &str) -> Vec<Target<'_>> {
+        let names = if self.fallback.is_empty() { &self.try_downstreams } else { &self.fallback };
+
+        names.iter()
+            .filter_map(|name| self.target(name))
+            .filter_map(|target| target.without(lost))
+            .collect()
+    }
+}
+
+/// Case-insensitive glob matching where `*` matches any amount of characters.
+fn wildcard_match(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let mut parts = pattern.split('*');
+
+    // There is always at least one part, and the first one has to be a prefix
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match host.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.is_empty()
+}
@@ -34,12 +34,12 @@ async fn main() -> Result<()> {
         Ok(config) => config,
         Err(e) => {
             error!("Failed to load config from '{}': {}", config_path, e);
-            return Err(e.into());
+            return Err(e);
         }
     };
     
     info!("Loaded config from {}", config_path);
 
-    let proxy = proxy::server::ProxyServer::new(config);
-    Ok(proxy.run().await?)
+    let proxy = proxy::server::ProxyServer::new(config)?;
+    proxy.run().await
 }
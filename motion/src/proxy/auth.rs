@@ -0,0 +1,118 @@
+use protocol::{packets::s2c::Property, uuid::UUID3};
+use serde::Deserialize;
+use sha1::{Sha1, Digest};
+
+use super::encryption::ProxyKeys;
+
+/// Everything needed to authenticate players in online-mode.
+pub struct Authenticator {
+    pub keys: ProxyKeys,
+    pub sessions: SessionService,
+}
+
+/// Player profile returned by the session server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GameProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<ProfileProperty>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+impl GameProfile {
+    pub fn uuid(&self) -> anyhow::Result<UUID3> {
+        UUID3::try_from(self.id.clone())
+    }
+
+    /// Properties (skin textures basically) in the form Login Success expects them.
+    pub fn login_properties(&self) -> Vec<Property> {
+        self.properties.iter().map(|property| Property {
+            name: property.name.clone(),
+            value: property.value.clone(),
+            signature: property.signature.clone(),
+        }).collect()
+    }
+}
+
+/// Client for the session server (`hasJoined` endpoint).
+pub struct SessionService {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl SessionService {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Ask the session server whether the player has joined with this server hash.
+    /// Returns `None` if the player is not authenticated.
+    pub async fn has_joined(&self, username: &str, server_hash: &str) -> anyhow::Result<Option<GameProfile>> {
+        let response = self.client
+            .get(format!("{}/session/minecraft/hasJoined", self.base_url))
+            .query(&[("username", username), ("serverId", server_hash)])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        Ok(Some(response.json().await?))
+    }
+}
+
+/// Minecraft's server hash: SHA-1 of the server id, shared secret and public key,
+/// formatted as a signed (two's complement) hex number.
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(server_id.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(public_key);
+    let mut hash: [u8; 20] = hasher.finalize().into();
+
+    let negative = hash[0] & 0x80 != 0;
+    if negative {
+        // Two's complement
+        let mut carry = true;
+        for byte in hash.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                carry = *byte == 0xff;
+                *byte = byte.wrapping_add(1);
+            }
+        }
+    }
+
+    let hex = hash.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    let hex = hex.trim_start_matches('0');
+
+    if negative {
+        format!("-{}", hex)
+    } else {
+        hex.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::server_hash;
+
+    #[test]
+    fn server_hash_matches_known_vectors() {
+        assert_eq!(server_hash("Notch", &[], &[]), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(server_hash("jeb_", &[], &[]), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(server_hash("simon", &[], &[]), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+}
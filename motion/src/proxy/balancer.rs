@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, atomic::{AtomicUsize, Ordering}},
+};
+
+use protocol::uuid::UUID3;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::config::{DownstreamConfig, GroupConfig, BalancingStrategy, HashKey};
+
+/// Who is being balanced.
+pub struct PlayerInfo<'a> {
+    pub username: &'a str,
+    pub uuid: UUID3,
+}
+
+/// Picks a member of a downstream group for a player.
+pub trait Balancer: Send + Sync {
+    /// `candidates` is never empty. Members that couldn't be reached
+    /// are left out and the balancer is asked again.
+    fn pick<'a>(&self, candidates: &[&'a DownstreamConfig], player: &PlayerInfo) -> &'a DownstreamConfig;
+}
+
+pub fn from_config(config: &GroupConfig, connections: Arc<ConnectionCounts>) -> Box<dyn Balancer> {
+    match config.strategy {
+        BalancingStrategy::RoundRobin => Box::<RoundRobin>::default(),
+        BalancingStrategy::LeastConnections => Box::new(LeastConnections { connections }),
+        BalancingStrategy::WeightedRandom => Box::new(WeightedRandom),
+        BalancingStrategy::ConsistentHash => Box::new(ConsistentHash { key: config.hash_key }),
+    }
+}
+
+#[derive(Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl Balancer for RoundRobin {
+    fn pick<'a>(&self, candidates: &[&'a DownstreamConfig], _player: &PlayerInfo) -> &'a DownstreamConfig {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        candidates[index % candidates.len()]
+    }
+}
+
+/// Picks the member with the fewest players relative to its weight.
+pub struct LeastConnections {
+    connections: Arc<ConnectionCounts>,
+}
+
+impl Balancer for LeastConnections {
+    fn pick<'a>(&self, candidates: &[&'a DownstreamConfig], _player: &PlayerInfo) -> &'a DownstreamConfig {
+        let load = |downstream: &DownstreamConfig| {
+            self.connections.get(&downstream.name) as f64 / downstream.weight.max(1) as f64
+        };
+
+        candidates.iter()
+            .min_by(|a, b| load(a).total_cmp(&load(b)))
+            .copied()
+            .unwrap_or(candidates[0])
+    }
+}
+
+pub struct WeightedRandom;
+
+impl Balancer for WeightedRandom {
+    fn pick<'a>(&self, candidates: &[&'a DownstreamConfig], _player: &PlayerInfo) -> &'a DownstreamConfig {
+        let total: u64 = candidates.iter().map(|downstream| downstream.weight as u64).sum();
+        let mut rng = rand::thread_rng();
+
+        if total == 0 {
+            return candidates[rng.gen_range(0..candidates.len())];
+        }
+
+        let mut point = rng.gen_range(0..total);
+        for downstream in candidates {
+            if point < downstream.weight as u64 {
+                return downstream;
+            }
+            point -= downstream.weight as u64;
+        }
+
+        candidates[candidates.len() - 1]
+    }
+}
+
+/// Weighted rendezvous hashing: a player keeps landing on the same member,
+/// and only the players of a member that goes away get moved.
+pub struct ConsistentHash {
+    key: HashKey,
+}
+
+impl Balancer for ConsistentHash {
+    fn pick<'a>(&self, candidates: &[&'a DownstreamConfig], player: &PlayerInfo) -> &'a DownstreamConfig {
+        let key = match self.key {
+            HashKey::Username => player.username.to_lowercase(),
+            HashKey::Uuid => player.uuid.to_string(),
+        };
+
+        let score = |downstream: &DownstreamConfig| {
+            // SHA-256 rather than std's hasher, which may change between Rust releases
+            // and would reshuffle players or make proxies disagree
+            let digest = Sha256::new()
+                .chain_update(key.as_bytes())
+                .chain_update([0])
+                .chain_update(downstream.name.as_bytes())
+                .finalize();
+            let mut hash = [0; 8];
+            hash.copy_from_slice(&digest[..8]);
+
+            // Uniform in (0, 1]
+            let uniform = ((u64::from_be_bytes(hash) >> 11) as f64 + 1.0) / ((1u64 << 53) as f64);
+            -(downstream.weight as f64) / uniform.ln()
+        };
+
+        candidates.iter()
+            .max_by(|a, b| score(a).total_cmp(&score(b)))
+            .copied()
+            .unwrap_or(candidates[0])
+    }
+}
+
+/// Players connected to each downstream.
+pub struct ConnectionCounts {
+    counts: HashMap<String, Arc<AtomicUsize>>,
+}
+
+impl ConnectionCounts {
+    pub fn new(downstreams: &[DownstreamConfig]) -> Self {
+        let counts = downstreams.iter()
+            .map(|downstream| (downstream.name.clone(), Arc::new(AtomicUsize::new(0))))
+            .collect();
+
+        Self { counts }
+    }
+
+    pub fn get(&self, name: &str) -> usize {
+        self.counts.get(name).map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    /// Count a player as connected to the downstream for as long as the slot lives.
+    pub fn slot(&self, name: &str) -> ConnectionSlot {
+        let count = self.counts.get(name).cloned().unwrap_or_default();
+        count.fetch_add(1, Ordering::Relaxed);
+
+        ConnectionSlot(count)
+    }
+}
+
+pub struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,35 +1,130 @@
 use std::net::SocketAddr;
 
-use tokio::net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream};
+use protocol::{
+    PacketReadExt, PacketWriteExt,
+    crypto::{EncryptedReader, EncryptedWriter},
+    error::ProtocolError,
+    packets::{Packet, C2SPacket, S2CPacket, c2s},
+};
+use tokio::{net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream}, sync::mpsc, io::AsyncWriteExt};
 
-use super::tunnel::TunnelPipe;
+use crate::config::DownstreamConfig;
 
-pub struct Upstream(pub OwnedReadHalf, pub OwnedWriteHalf, pub SocketAddr);
+use super::{tunnel::TunnelPipe, player::SwitchRequest, balancer::ConnectionSlot, proxy_protocol};
 
+pub struct Upstream(
+    pub EncryptedReader<OwnedReadHalf>,
+    pub EncryptedWriter<OwnedWriteHalf>,
+    pub SocketAddr,
+);
+
+/// Connection to a downstream server on behalf of a player.
 pub struct ProxyConnection {
     pub remote_addr: SocketAddr,
-    
-    pub upstream: (OwnedReadHalf, OwnedWriteHalf),
+    pub server: DownstreamConfig,
+
     pub downstream: (OwnedReadHalf, OwnedWriteHalf),
+    /// Counts the player as connected to the downstream
+    pub slot: ConnectionSlot,
 }
 
 impl ProxyConnection {
-    /// Initialize a new proxy connection struct 
+    /// Initialize a new proxy connection struct
     /// with creating a TCP connection to the downstream server.
-    pub async fn init(upstream: Upstream, destination: SocketAddr) -> Self {
-        let downstream = TcpStream::connect(destination).await.unwrap();
+    pub async fn init(remote_addr: SocketAddr, server: &DownstreamConfig, slot: ConnectionSlot) -> anyhow::Result<Self> {
+        let mut downstream = TcpStream::connect(&server.address).await?;
 
-        Self {
-            remote_addr: upstream.2,
-            
-            upstream: (upstream.0, upstream.1),
-            downstream: downstream.into_split(),
+        if server.proxy_protocol {
+            let header = proxy_protocol::v2_header(remote_addr, downstream.peer_addr()?);
+            downstream.write_all(&header).await?;
         }
+
+        Ok(Self {
+            remote_addr,
+            server: server.clone(),
+
+            downstream: downstream.into_split(),
+            slot,
+        })
     }
 
     /// Establish proxy connection (create a tunnel basically).
-    pub async fn establish(&mut self) {
-        let mut tunnel = TunnelPipe::new(self.remote_addr);
-        tunnel.establish_pipes(&mut self.upstream, &mut self.downstream).await;
+    /// The handshake and login start the client already sent to the proxy
+    /// are replayed to the downstream server first.
+    pub async fn establish(
+        mut self,
+        upstream: &mut Upstream,
+        tunnel: TunnelPipe,
+        switches: &mut mpsc::Receiver<SwitchRequest>,
+    ) -> anyhow::Result<()> {
+        self.send_login(&tunnel).await?;
+
+        tunnel.establish_pipes((&mut upstream.0, &mut upstream.1), self, switches).await;
+
+        Ok(())
+    }
+
+    /// Send the (forwarding) handshake and the login start of the player.
+    async fn send_login(&mut self, tunnel: &TunnelPipe) -> anyhow::Result<()> {
+        let mut handshake = tunnel.state().handshake.clone()
+            .ok_or_else(|| anyhow::anyhow!("Handshake packet not received"))?;
+        tunnel.forward_handshake(&mut handshake, &self.server)?;
+        let handshake = Packet::C2S(C2SPacket::Handshake(handshake));
+
+        let login_start = tunnel.login_start()
+            .ok_or_else(|| anyhow::anyhow!("Login start packet not received"))?;
+        let login_start = Packet::C2S(C2SPacket::LoginStart(login_start.clone()));
+
+        let state = tunnel.login_state();
+        self.downstream.1.write_packet(&handshake, &state).await?;
+        self.downstream.1.write_packet(&login_start, &state).await?;
+
+        Ok(())
+    }
+
+    /// Log in to the downstream server on the proxy side. Used when switching servers,
+    /// since the client is already past the login state.
+    /// Returns the compression threshold the downstream server asked for.
+    pub async fn login(&mut self, tunnel: &TunnelPipe) -> anyhow::Result<Option<usize>> {
+        self.send_login(tunnel).await?;
+
+        let mut state = tunnel.login_state();
+        loop {
+            let packet = match self.downstream.0.read_packet_s2c(&state).await {
+                Ok(packet) => packet,
+                Err(ProtocolError::UnknownPacketId { context, .. }) => {
+                    return Err(anyhow::anyhow!("Unexpected {} while logging in", context));
+                },
+                Err(e) => return Err(e.into()),
+            };
+
+            match packet {
+                Packet::S2C(S2CPacket::SetCompression(packet)) => {
+                    state.compression_threshold = usize::try_from(packet.threshold).ok();
+                },
+                Packet::S2C(S2CPacket::LoginSuccess(_)) => {
+                    if state.has_configuration_state() {
+                        let acknowledged = Packet::C2S(C2SPacket::LoginAcknowledged(c2s::LoginAcknowledged));
+                        self.downstream.1.write_packet(&acknowledged, &state).await?;
+                    }
+
+                    return Ok(state.compression_threshold);
+                },
+                Packet::S2C(S2CPacket::LoginDisconnect(packet)) => {
+                    return Err(anyhow::anyhow!("Kicked while logging in: {}", packet.reason.to_plain_text()));
+                },
+                Packet::S2C(S2CPacket::LoginPluginRequest(request)) => {
+                    // Nobody else is there to answer, so anything but forwarding is declined
+                    let response = tunnel.answer_login_plugin(&request, &self.server).await?
+                        .unwrap_or(c2s::LoginPluginResponse { message_id: request.message_id, data: None });
+
+                    self.downstream.1.write_packet(&Packet::C2S(C2SPacket::LoginPluginResponse(response)), &state).await?;
+                },
+                Packet::S2C(S2CPacket::EncryptionRequest(_)) => {
+                    return Err(anyhow::anyhow!("Downstream servers have to run in offline mode"));
+                },
+                packet => return Err(anyhow::anyhow!("Unexpected packet while logging in: {:?}", packet)),
+            }
+        }
     }
 }
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use super::server::ProxyContext;
+
+/// Read commands from the standard input until it's closed.
+pub async fn run_console(context: Arc<ProxyContext>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if let Err(e) = handle_command(&context, line.trim()).await {
+            warn!("{}", e);
+        }
+    }
+}
+
+async fn handle_command(context: &ProxyContext, line: &str) -> anyhow::Result<()> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+
+    match args.as_slice() {
+        [] => {},
+        ["list"] => {
+            let players = context.player_names();
+            info!("{} player(s) online: {}", players.len(), players.join(", "));
+        },
+        ["servers"] => {
+            for downstream in &context.config.downstreams {
+                let health = context.health.health(&downstream.name);
+                let state = match (health.healthy, health.latency) {
+                    (false, _) => "down".to_string(),
+                    (true, Some(latency)) => format!("up, {} ms", latency.as_millis()),
+                    (true, None) => "unchecked".to_string(),
+                };
+
+                info!(
+                    "{} ({}): {}, {} player(s)",
+                    downstream.name, downstream.address, state, context.connections.get(&downstream.name)
+                );
+            }
+        },
+        ["send", username, target] => {
+            let player = context.player(username)
+                .ok_or_else(|| anyhow::anyhow!("{} is not online", username))?;
+            let target = context.config.target(target)
+                .ok_or_else(|| anyhow::anyhow!("Unknown downstream or group '{}'", target))?;
+
+            let connection = context.connect(&target, player.remote_addr, &player.info()).await?;
+            player.switch(connection).await?;
+        },
+        _ => {
+            return Err(anyhow::anyhow!("Unknown command '{}'. Available commands: list, servers, send <player> <downstream or group>", line));
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use rsa::{RsaPrivateKey, RsaPublicKey, Pkcs1v15Encrypt, Pkcs1v15Sign, pkcs8::{EncodePublicKey, DecodePublicKey}};
+use sha2::{Digest, Sha256};
+
+/// RSA keypair the proxy uses for the online-mode encryption handshake.
+/// Generated once on startup, just like the vanilla server does.
+pub struct ProxyKeys {
+    private_key: RsaPrivateKey,
+    public_key_der: Vec<u8>,
+}
+
+impl ProxyKeys {
+    pub fn generate() -> anyhow::Result<Self> {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)?;
+        let public_key_der = private_key.to_public_key().to_public_key_der()?.into_vec();
+
+        Ok(Self { private_key, public_key_der })
+    }
+
+    /// Public key in the X.509 DER format the client expects.
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(self.private_key.decrypt(Pkcs1v15Encrypt, data)?)
+    }
+}
+
+/// Check a SHA256withRSA signature made with the profile key of a 1.19 - 1.19.2 client,
+/// `public_key_der` being the X.509 DER key of its login start.
+pub fn verify_signature(public_key_der: &[u8], message: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+    let public_key = RsaPublicKey::from_public_key_der(public_key_der)?;
+    public_key.verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(message), signature)?;
+
+    Ok(())
+}
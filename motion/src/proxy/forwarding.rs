@@ -0,0 +1,64 @@
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use protocol::{packets::s2c::Property, utils::DataWriteExt, uuid::UUID3};
+use serde_json::json;
+use sha2::Sha256;
+
+/// Login plugin channel Velocity-compatible backends ask for the player info on
+pub const VELOCITY_PLAYER_INFO_CHANNEL: &str = "velocity:player_info";
+
+/// Property carrying the BungeeGuard token, lets legacy forwarding backends tell the proxy from spoofers
+pub const BUNGEEGUARD_TOKEN_PROPERTY: &str = "bungeeguard-token";
+
+/// The version without chat signing keys, understood by every backend that supports modern forwarding
+const MODERN_FORWARDING_VERSION: i32 = 1;
+
+/// Server address of the handshake with the player info packed in, the legacy (BungeeCord) way:
+/// `host \0 player address \0 uuid \0 properties`.
+pub fn legacy_server_address(host: &str, address: IpAddr, uuid: UUID3, properties: &[Property]) -> anyhow::Result<String> {
+    let properties: Vec<_> = properties.iter().map(|property| match &property.signature {
+        Some(signature) => json!({ "name": property.name, "value": property.value, "signature": signature }),
+        None => json!({ "name": property.name, "value": property.value }),
+    }).collect();
+
+    Ok([
+        host.to_string(),
+        address.to_string(),
+        uuid.to_simple_string(),
+        serde_json::to_string(&properties)?,
+    ].join("\x00"))
+}
+
+/// Player info for Velocity modern forwarding, signed with the secret shared with the backend.
+pub async fn velocity_player_info(
+    secret: &str,
+    address: IpAddr,
+    uuid: UUID3,
+    username: &str,
+    properties: &[Property],
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = vec![];
+    data.write_varint(MODERN_FORWARDING_VERSION).await?;
+    data.write_string(&address.to_string()).await?;
+    data.write_uuid(uuid).await?;
+    data.write_string(username).await?;
+
+    data.write_varint(properties.len() as i32).await?;
+    for property in properties {
+        data.write_string(&property.name).await?;
+        data.write_string(&property.value).await?;
+        data.write_bool(property.signature.is_some()).await?;
+        if let Some(signature) = &property.signature {
+            data.write_string(signature).await?;
+        }
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(&data);
+
+    let mut signed = mac.finalize().into_bytes().to_vec();
+    signed.extend(data);
+
+    Ok(signed)
+}
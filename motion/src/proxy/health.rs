@@ -0,0 +1,154 @@
+use std::{collections::HashMap, sync::{Arc, RwLock}, time::{Duration, Instant}};
+
+use protocol::{
+    PacketReadExt, PacketWriteExt, State, GameStateEnum,
+    packets::{Packet, C2SPacket, S2CPacket, c2s}, version::ProtocolVersion,
+};
+use tokio::{net::TcpStream, task::JoinSet, io::AsyncWriteExt};
+
+use crate::config::{DownstreamConfig, HealthCheckConfig};
+
+use super::proxy_protocol;
+
+/// Protocol version sent in the status handshake, servers answer regardless of it
+const PING_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::V1_20;
+
+/// Health of a downstream as seen by the status pings.
+#[derive(Debug, Clone, Copy)]
+pub struct Health {
+    pub healthy: bool,
+    /// Round trip of the last successful ping
+    pub latency: Option<Duration>,
+    /// Successful or failed pings in a row
+    streak: u32,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        // Downstreams are trusted until they fail their first checks
+        Self { healthy: true, latency: None, streak: 0 }
+    }
+}
+
+/// Status-pings every downstream periodically, so routing can skip the dead ones.
+pub struct HealthChecker {
+    config: HealthCheckConfig,
+    downstreams: Vec<DownstreamConfig>,
+    health: RwLock<HashMap<String, Health>>,
+}
+
+impl HealthChecker {
+    pub fn new(config: &HealthCheckConfig, downstreams: &[DownstreamConfig]) -> Self {
+        let health = downstreams.iter()
+            .map(|downstream| (downstream.name.clone(), Health::default()))
+            .collect();
+
+        Self {
+            config: config.clone(),
+            downstreams: downstreams.to_vec(),
+            health: RwLock::new(health),
+        }
+    }
+
+    pub fn health(&self, name: &str) -> Health {
+        self.health.read().unwrap().get(name).copied().unwrap_or_default()
+    }
+
+    pub fn is_healthy(&self, name: &str) -> bool {
+        !self.config.enabled || self.health(name).healthy
+    }
+
+    /// Check all the downstreams on every interval, forever.
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+
+        loop {
+            interval.tick().await;
+
+            let mut checks = JoinSet::new();
+            for downstream in &self.downstreams {
+                let downstream = downstream.clone();
+                checks.spawn(async move {
+                    let result = tokio::time::timeout(timeout, ping(&downstream)).await
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out")));
+
+                    (downstream.name, result)
+                });
+            }
+
+            while let Some(Ok((name, result))) = checks.join_next().await {
+                self.record(&name, result);
+            }
+        }
+    }
+
+    fn record(&self, name: &str, result: anyhow::Result<Duration>) {
+        let mut health = self.health.write().unwrap();
+        let health = health.entry(name.to_string()).or_default();
+
+        let succeeded = result.is_ok();
+        if let Ok(latency) = &result {
+            health.latency = Some(*latency);
+        }
+
+        // The streak counts the results that disagree with the current state
+        if succeeded != health.healthy {
+            health.streak += 1;
+        } else {
+            health.streak = 0;
+        }
+
+        let threshold = if health.healthy { self.config.fall } else { self.config.rise };
+        if health.streak >= threshold.max(1) {
+            health.healthy = succeeded;
+            health.streak = 0;
+
+            match result {
+                Ok(latency) => info!("Downstream {} is up ({} ms)", name, latency.as_millis()),
+                Err(e) => warn!("Downstream {} is down: {}", name, e),
+            }
+        }
+    }
+}
+
+/// Do a server list ping and return the ping/pong round trip.
+async fn ping(downstream: &DownstreamConfig) -> anyhow::Result<Duration> {
+    let (host, port) = downstream.address.rsplit_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Address has no port: {}", downstream.address))?;
+
+    let stream = TcpStream::connect(&downstream.address).await?;
+    let (mut reader, mut writer) = stream.into_split();
+
+    if downstream.proxy_protocol {
+        writer.write_all(&proxy_protocol::v2_local_header()).await?;
+    }
+
+    let handshake = c2s::Handshake {
+        protocol_version: PING_PROTOCOL_VERSION.0,
+        server_address: host.to_string(),
+        server_port: port.parse()?,
+        next_state: c2s::NextState::Status,
+    };
+    let state = State {
+        handshake: Some(handshake.clone()),
+        state: GameStateEnum::Status,
+        compression_threshold: None,
+        ..Default::default()
+    };
+
+    writer.write_packet(&Packet::C2S(C2SPacket::Handshake(handshake)), &state).await?;
+    writer.write_packet(&Packet::C2S(C2SPacket::StatusRequest(c2s::StatusRequest)), &state).await?;
+    match reader.read_packet_s2c(&state).await? {
+        Packet::S2C(S2CPacket::StatusResponse(_)) => {},
+        packet => return Err(anyhow::anyhow!("Expected status response, got {:?}", packet)),
+    }
+
+    let start = Instant::now();
+    let payload = rand::random();
+    writer.write_packet(&Packet::C2S(C2SPacket::PingRequest(c2s::PingRequest { payload })), &state).await?;
+    match reader.read_packet_s2c(&state).await? {
+        Packet::S2C(S2CPacket::PongResponse(pong)) if pong.payload == payload => Ok(start.elapsed()),
+        packet => Err(anyhow::anyhow!("Expected pong response, got {:?}", packet)),
+    }
+}
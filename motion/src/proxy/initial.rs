@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use protocol::{
+    PacketReadExt, PacketWriteExt, State, GameStateEnum,
+    chat::ChatComponent,
+    packets::{Packet, C2SPacket, S2CPacket, s2c, c2s::SignatureData},
+};
+
+use super::{
+    connection::{ProxyConnection, Upstream}, server::ProxyContext,
+    tunnel::{self, TunnelPipe}, encryption::{self, ProxyKeys}, auth::server_hash, player::PlayerHandle,
+    balancer::PlayerInfo,
+};
+
+/// Handles a freshly accepted client until it needs a downstream server:
+/// answers server list pings and authenticates logins.
+pub struct InitialHandler {
+    upstream: Upstream,
+    context: Arc<ProxyContext>,
+    tunnel: TunnelPipe,
+}
+
+impl InitialHandler {
+    pub fn new(upstream: Upstream, context: Arc<ProxyContext>) -> Self {
+        let tunnel = TunnelPipe::new(upstream.2, context.clone());
+
+        Self {
+            upstream,
+            context,
+            tunnel,
+        }
+    }
+
+    pub async fn handle(mut self) -> anyhow::Result<()> {
+        let handshake = self.upstream.0.read_packet_c2s(self.tunnel.state()).await?;
+        if !matches!(handshake, Packet::C2S(C2SPacket::Handshake(_))) {
+            return Err(anyhow::anyhow!("Expected handshake packet, got {:?}", handshake));
+        }
+        self.tunnel.update_state(&handshake);
+
+        match self.tunnel.state().state {
+            GameStateEnum::Status => self.handle_status().await,
+            GameStateEnum::Login => self.handle_login().await,
+            state => Err(anyhow::anyhow!("Unexpected state after handshake: {:?}", state)),
+        }
+    }
+
+    /// Virtual host the player connected with, without the Forge marker and trailing dot.
+    fn host(&self) -> String {
+        let handshake = self.tunnel.state().handshake.as_ref().unwrap();
+        normalize_host(&handshake.server_address)
+    }
+
+    /// Answer the server list ping with the configured MOTD.
+    async fn handle_status(&mut self) -> anyhow::Result<()> {
+        let state = self.tunnel.state().clone();
+        let protocol_version = state.handshake.as_ref().unwrap().protocol_version;
+        let host = self.host();
+        let mut responded = false;
+
+        loop {
+            let packet = match self.upstream.0.read_packet_c2s(&state).await {
+                Ok(packet) => packet,
+                // Older clients just close the connection instead of pinging
+                Err(_) if responded => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+
+            match packet {
+                Packet::C2S(C2SPacket::StatusRequest(_)) => {
+                    let status = self.context.motd(&host).status(
+                        protocol_version,
+                        self.context.online_players(),
+                    );
+
+                    let response = Packet::S2C(S2CPacket::StatusResponse(s2c::StatusResponse { status }));
+                    self.upstream.1.write_packet(&response, &state).await?;
+                    responded = true;
+                },
+                Packet::C2S(C2SPacket::PingRequest(ping)) => {
+                    let pong = Packet::S2C(S2CPacket::PongResponse(s2c::PongResponse { payload: ping.payload }));
+                    self.upstream.1.write_packet(&pong, &state).await?;
+
+                    return Ok(());
+                },
+                packet => return Err(anyhow::anyhow!("Unexpected packet in status state: {:?}", packet)),
+            }
+        }
+    }
+
+    /// Read the login start, authenticate the client if needed
+    /// and hand it over to a downstream server.
+    async fn handle_login(mut self) -> anyhow::Result<()> {
+        let login_start = self.upstream.0.read_packet_c2s(self.tunnel.state()).await?;
+        let (username, profile_key) = match &login_start {
+            Packet::C2S(C2SPacket::LoginStart(packet)) => (packet.username.clone(), packet.signature_data.clone()),
+            packet => return Err(anyhow::anyhow!("Expected login start packet, got {:?}", packet)),
+        };
+        self.tunnel.update_state(&login_start);
+
+        let context = self.context.clone();
+        if let Some(authenticator) = &context.authenticator {
+            let state = self.tunnel.state().clone();
+            let shared_secret = self.encrypt(&state, &authenticator.keys, profile_key.as_ref()).await?;
+            let hash = server_hash("", &shared_secret, authenticator.keys.public_key_der());
+
+            let profile = match authenticator.sessions.has_joined(&username, &hash).await {
+                Ok(Some(profile)) => profile,
+                result => {
+                    // What vanilla servers tell players they couldn't authenticate
+                    let reason = ChatComponent::text("Failed to verify username!");
+                    tunnel::kick(self.tunnel.state(), &mut self.upstream.1, reason).await?;
+
+                    return Err(result.err().unwrap_or_else(|| {
+                        anyhow::anyhow!("{} failed to authenticate with the session server", username)
+                    }));
+                },
+            };
+
+            info!("Authenticated {} ({})", profile.name, profile.id);
+            self.tunnel.set_profile(profile)?;
+        }
+
+        let connection = match self.connect(&username).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                let targets = context.config.initial_targets(&self.host());
+                let server = targets.first().map_or("", |target| target.name());
+                let reason = ChatComponent::text(context.config.messages.server_unavailable(server));
+                tunnel::kick(self.tunnel.state(), &mut self.upstream.1, reason).await?;
+
+                return Err(e);
+            },
+        };
+
+        info!("{} connected to {}", username, connection.server.name);
+
+        let (player, mut switches) = PlayerHandle::new(username, self.tunnel.player_uuid()?, self.upstream.2);
+        context.register_player(player.clone());
+        let result = connection.establish(&mut self.upstream, self.tunnel, &mut switches).await;
+        context.unregister_player(&player);
+
+        result
+    }
+
+    /// Connect to the forced or default downstream,
+    /// trying the next ones from the try list if it's unreachable.
+    async fn connect(&self, username: &str) -> anyhow::Result<ProxyConnection> {
+        let player = PlayerInfo { username, uuid: self.tunnel.player_uuid()? };
+
+        for target in self.context.config.initial_targets(&self.host()) {
+            if let Ok(connection) = self.context.connect(&target, self.upstream.2, &player).await {
+                return Ok(connection);
+            }
+        }
+
+        Err(anyhow::anyhow!("No downstream server is reachable"))
+    }
+
+    /// Do the encryption handshake with the client and enable encryption
+    /// on the client connection. The downstream connection stays unencrypted.
+    /// 1.19 - 1.19.2 clients with a profile key sign the verify token instead of encrypting it.
+    /// Returns the shared secret.
+    async fn encrypt(&mut self, state: &State, keys: &ProxyKeys, profile_key: Option<&SignatureData>) -> anyhow::Result<Vec<u8>> {
+        let verify_token: [u8; 4] = rand::random();
+
+        let request = Packet::S2C(S2CPacket::EncryptionRequest(s2c::EncryptionRequest {
+            server_id: String::new(),
+            public_key: keys.public_key_der().to_vec(),
+            verify_token: verify_token.to_vec(),
+            should_authenticate: Some(true),
+        }));
+        self.upstream.1.write_packet(&request, state).await?;
+
+        let response = match self.upstream.0.read_packet_c2s(state).await? {
+            Packet::C2S(C2SPacket::EncryptionResponse(response)) => response,
+            packet => return Err(anyhow::anyhow!("Expected encryption response packet, got {:?}", packet)),
+        };
+
+        match (response.verify_token, response.signature, profile_key) {
+            (Some(encrypted_token), _, _) => {
+                if keys.decrypt(&encrypted_token)? != verify_token {
+                    return Err(anyhow::anyhow!("Verify token mismatch"));
+                }
+            },
+            (None, Some((salt, signature)), Some(profile_key)) => {
+                let mut message = verify_token.to_vec();
+                message.extend_from_slice(&salt.to_be_bytes());
+
+                encryption::verify_signature(&profile_key.public_key, &message, &signature)
+                    .map_err(|e| anyhow::anyhow!("Invalid verify token signature: {}", e))?;
+            },
+            _ => return Err(anyhow::anyhow!("Signed encryption response without a profile key")),
+        }
+
+        let shared_secret = keys.decrypt(&response.shared_secret)?;
+        self.upstream.0.enable_encryption(&shared_secret)?;
+        self.upstream.1.enable_encryption(&shared_secret)?;
+
+        Ok(shared_secret)
+    }
+}
+
+/// Forge appends `\0FML\0` (or `\0FML2\0`, `\0FML3\0`, `\0FORGE`) to the server address,
+/// and some clients keep the trailing dot of a fully qualified domain name.
+pub fn normalize_host(server_address: &str) -> String {
+    let host = server_address.split('\0').next().unwrap_or_default();
+
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
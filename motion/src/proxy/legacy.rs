@@ -0,0 +1,117 @@
+use std::{io::ErrorKind, time::Duration};
+
+use tokio::{net::TcpStream, io::{AsyncReadExt, AsyncWriteExt}};
+
+use super::server::ProxyContext;
+
+/// Protocol version reported to legacy clients, old clients show the version name
+/// in red for anything they don't know, same as BungeeCord does.
+const LEGACY_PROTOCOL_VERSION: i32 = 127;
+
+/// How long to wait for the next byte of a legacy ping. Clients write the bytes
+/// one by one, so a byte that never comes tells the variants apart.
+const LEGACY_READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Variants of the server list ping sent by pre-1.7 clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyPing {
+    /// Beta 1.8 - 1.3, just `0xFE`
+    Beta,
+    /// 1.4 - 1.5, `0xFE 0x01`
+    V1_4,
+    /// 1.6, `0xFE 0x01 0xFA` followed by a `MC|PingHost` plugin message
+    V1_6,
+}
+
+impl LegacyPing {
+    /// Read the whole legacy ping after the `0xFE` has been peeked.
+    /// Everything the client sent is consumed, closing a socket with unread
+    /// data would reset the connection before the client reads the answer.
+    async fn read(socket: &mut TcpStream) -> anyhow::Result<Self> {
+        socket.read_u8().await?;
+
+        if read_byte(socket).await? != Some(0x01) {
+            return Ok(Self::Beta);
+        }
+        if read_byte(socket).await? != Some(0xFA) {
+            return Ok(Self::V1_4);
+        }
+
+        // `MC|PingHost` plugin message: channel as UTF-16BE and the payload,
+        // only there to be skipped since the answer doesn't depend on it.
+        let _ = tokio::time::timeout(LEGACY_READ_TIMEOUT, async {
+            let channel = socket.read_u16().await?;
+            skip(socket, u64::from(channel) * 2).await?;
+            let length = socket.read_u16().await?;
+            skip(socket, u64::from(length)).await
+        }).await;
+
+        Ok(Self::V1_6)
+    }
+}
+
+/// Check whether the client sent a legacy ping and answer it if so.
+/// Nothing is consumed from the socket if it's a regular connection.
+/// Modern handshakes never start with `0xFE` since that would be a huge length prefix.
+/// Returns `true` if the ping got answered and the connection should be closed.
+pub async fn handle_legacy_ping(socket: &mut TcpStream, context: &ProxyContext) -> anyhow::Result<bool> {
+    let mut first = [0u8; 1];
+    let read = socket.peek(&mut first).await?;
+
+    if read == 0 || first[0] != 0xFE {
+        return Ok(false);
+    }
+    let ping = LegacyPing::read(socket).await?;
+
+    let motd = &context.motd;
+    let description = motd.description().to_plain_text();
+    let online = context.online_players();
+
+    let response = match ping {
+        LegacyPing::Beta => {
+            // The separator can't be used in the description in this format
+            format!("{}§{}§{}", description.replace('§', ""), online, motd.max_players())
+        },
+        LegacyPing::V1_4 | LegacyPing::V1_6 => {
+            format!(
+                "§1\0{}\0{}\0{}\0{}\0{}", 
+                LEGACY_PROTOCOL_VERSION, motd.version_name(), description, online, motd.max_players()
+            )
+        },
+    };
+
+    socket.write_all(&encode_kick(&response)).await?;
+    socket.shutdown().await?;
+
+    Ok(true)
+}
+
+/// Read the next byte, `None` if the client doesn't send one in time.
+async fn read_byte(socket: &mut TcpStream) -> anyhow::Result<Option<u8>> {
+    match tokio::time::timeout(LEGACY_READ_TIMEOUT, socket.read_u8()).await {
+        Ok(Ok(byte)) => Ok(Some(byte)),
+        Ok(Err(e)) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Ok(None),
+    }
+}
+
+async fn skip(socket: &mut TcpStream, length: u64) -> std::io::Result<()> {
+    tokio::io::copy(&mut (&mut *socket).take(length), &mut tokio::io::sink()).await?;
+
+    Ok(())
+}
+
+/// Legacy kick packet: `0xFF`, length in UTF-16 code units, UTF-16BE string.
+fn encode_kick(message: &str) -> Vec<u8> {
+    let chars: Vec<u16> = message.encode_utf16().collect();
+
+    let mut data = Vec::with_capacity(3 + chars.len() * 2);
+    data.push(0xFF);
+    data.extend_from_slice(&(chars.len() as u16).to_be_bytes());
+    for char in chars {
+        data.extend_from_slice(&char.to_be_bytes());
+    }
+
+    data
+}
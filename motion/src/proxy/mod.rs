@@ -1,3 +1,14 @@
+pub mod auth;
+pub mod balancer;
 pub mod connection;
+pub mod console;
+pub mod encryption;
+pub mod forwarding;
+pub mod health;
+pub mod initial;
+pub mod legacy;
+pub mod player;
+pub mod proxy_protocol;
 pub mod server;
+pub mod status;
 pub mod tunnel;
@@ -0,0 +1,51 @@
+use std::net::SocketAddr;
+
+use protocol::uuid::UUID3;
+use tokio::sync::{mpsc, oneshot};
+
+use super::{connection::ProxyConnection, balancer::PlayerInfo};
+
+/// Request to move a player to another downstream.
+pub struct SwitchRequest {
+    /// Fresh connection to the new downstream, the player isn't logged in there yet
+    pub connection: ProxyConnection,
+    pub result: oneshot::Sender<anyhow::Result<()>>,
+}
+
+/// Handle to a player connected to a downstream, used to move them between downstreams.
+#[derive(Clone)]
+pub struct PlayerHandle {
+    pub username: String,
+    pub uuid: UUID3,
+    pub remote_addr: SocketAddr,
+    switches: mpsc::Sender<SwitchRequest>,
+}
+
+impl PlayerHandle {
+    pub fn new(username: String, uuid: UUID3, remote_addr: SocketAddr) -> (Self, mpsc::Receiver<SwitchRequest>) {
+        let (switches, receiver) = mpsc::channel(4);
+
+        (Self { username, uuid, remote_addr, switches }, receiver)
+    }
+
+    pub fn info(&self) -> PlayerInfo<'_> {
+        PlayerInfo { username: &self.username, uuid: self.uuid }
+    }
+
+    /// Move the player to the downstream of the connection, keeping them connected to the proxy.
+    /// Resolves once the player is logged in to the new downstream.
+    pub async fn switch(&self, connection: ProxyConnection) -> anyhow::Result<()> {
+        let (result, receiver) = oneshot::channel();
+        let request = SwitchRequest { connection, result };
+
+        self.switches.send(request).await
+            .map_err(|_| anyhow::anyhow!("{} is not connected anymore", self.username))?;
+
+        receiver.await
+            .map_err(|_| anyhow::anyhow!("{} disconnected while switching servers", self.username))?
+    }
+
+    pub fn is_same(&self, other: &PlayerHandle) -> bool {
+        self.switches.same_channel(&other.switches)
+    }
+}
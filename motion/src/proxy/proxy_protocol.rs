@@ -0,0 +1,127 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Signature every v2 header starts with
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest possible v1 header, including the CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+/// v2 header telling the downstream the connection is proxied for `source`.
+pub fn v2_header(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    // Version 2, PROXY command
+    header.push(0x21);
+
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+            header.push(0x11);
+            header.extend(12u16.to_be_bytes());
+            header.extend(source_ip.octets());
+            header.extend(destination_ip.octets());
+        },
+        // Mixed families have to be sent as IPv6
+        (source_ip, destination_ip) => {
+            header.push(0x21);
+            header.extend(36u16.to_be_bytes());
+            header.extend(to_ipv6(source_ip).octets());
+            header.extend(to_ipv6(destination_ip).octets());
+        },
+    }
+
+    header.extend(source.port().to_be_bytes());
+    header.extend(destination.port().to_be_bytes());
+    header
+}
+
+/// v2 header for connections the proxy makes on its own behalf, like health checks.
+pub fn v2_local_header() -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    // Version 2, LOCAL command, no addresses
+    header.extend([0x20, 0x00, 0x00, 0x00]);
+    header
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Read the PROXY protocol header (v1 or v2) a load balancer sends before anything else.
+/// Returns the address of the client, `None` if the header doesn't carry one
+/// (v1 `UNKNOWN`, v2 `LOCAL` connections like health checks, non-TCP families).
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> anyhow::Result<Option<SocketAddr>> {
+    // Even the shortest v1 header is longer than the v2 signature
+    let mut start = [0u8; 12];
+    reader.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(reader).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(reader, &start).await
+    } else {
+        Err(anyhow::anyhow!("Missing PROXY protocol header"))
+    }
+}
+
+/// `PROXY TCP4 <source> <destination> <source port> <destination port>\r\n`
+async fn read_v1<R: AsyncRead + Unpin>(reader: &mut R, start: &[u8]) -> anyhow::Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+
+    // Byte by byte, anything after the header belongs to the Minecraft connection
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(anyhow::anyhow!("PROXY protocol v1 header is too long"));
+        }
+        line.push(reader.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])?;
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            Ok(Some(SocketAddr::new(source.parse()?, source_port.parse()?)))
+        },
+        _ => Err(anyhow::anyhow!("Invalid PROXY protocol v1 header: {}", line)),
+    }
+}
+
+/// Binary header: version and command, address family, length, then the addresses and TLVs.
+async fn read_v2<R: AsyncRead + Unpin>(reader: &mut R) -> anyhow::Result<Option<SocketAddr>> {
+    let version_command = reader.read_u8().await?;
+    let family = reader.read_u8().await?;
+    let length = reader.read_u16().await? as usize;
+
+    let mut data = vec![0u8; length];
+    reader.read_exact(&mut data).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(anyhow::anyhow!("Unsupported PROXY protocol version {}", version_command >> 4));
+    }
+
+    // LOCAL, the connection was made by the load balancer itself
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+
+    let source = match family {
+        // TCP over IPv4
+        0x11 if data.len() >= 12 => {
+            let ip = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([data[8], data[9]]))
+        },
+        // TCP over IPv6
+        0x21 if data.len() >= 36 => {
+            let ip: [u8; 16] = data[..16].try_into()?;
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), u16::from_be_bytes([data[32], data[33]]))
+        },
+        0x11 | 0x21 => return Err(anyhow::anyhow!("PROXY protocol v2 addresses are truncated")),
+        _ => return Ok(None),
+    };
+
+    Ok(Some(source))
+}
@@ -1,33 +1,186 @@
+use std::{sync::{Arc, Mutex}, collections::HashMap, net::SocketAddr, time::Duration};
+
+use protocol::crypto::{EncryptedReader, EncryptedWriter};
 use tokio::net::TcpListener;
-use crate::config::Configuration;
-use super::connection::{ProxyConnection, Upstream};
+use crate::config::{Configuration, Target};
+use super::{
+    connection::ProxyConnection, balancer::{self, Balancer, ConnectionCounts, PlayerInfo},
+    connection::Upstream, initial::InitialHandler, encryption::ProxyKeys,
+    auth::{Authenticator, SessionService}, status::Motd, legacy::handle_legacy_ping, health::HealthChecker,
+    player::PlayerHandle, console::run_console, proxy_protocol,
+};
+
+/// State shared between all the connections of the proxy.
+pub struct ProxyContext {
+    pub config: Configuration,
+    pub authenticator: Option<Authenticator>,
+    pub motd: Motd,
+    /// Status of downstreams having their own MOTD for forced hosts
+    pub downstream_motds: HashMap<String, Motd>,
+    /// Players that are connected to a downstream server, by lowercase username
+    players: Mutex<HashMap<String, PlayerHandle>>,
+    pub connections: Arc<ConnectionCounts>,
+    /// Balancer of each downstream group
+    balancers: HashMap<String, Box<dyn Balancer>>,
+    pub health: Arc<HealthChecker>,
+}
+
+impl ProxyContext {
+    /// Status to answer with for the (normalized) virtual host.
+    pub fn motd(&self, host: &str) -> &Motd {
+        self.config.forced_target(host)
+            .and_then(|target| self.downstream_motds.get(target.name()))
+            .unwrap_or(&self.motd)
+    }
+
+    /// Connect to the target downstream. For groups the balancer picks the member,
+    /// and the other members are tried if it's unreachable. Unhealthy downstreams are skipped.
+    pub async fn connect(&self, target: &Target<'_>, remote_addr: SocketAddr, player: &PlayerInfo<'_>) -> anyhow::Result<ProxyConnection> {
+        let mut candidates = match target {
+            Target::Downstream(downstream) => vec![*downstream],
+            Target::Group(_, members) => members.clone(),
+        };
+        candidates.retain(|candidate| self.health.is_healthy(&candidate.name));
+        let connect_timeout = Duration::from_secs(self.config.connect_timeout_secs);
+
+        while !candidates.is_empty() {
+            let server = match self.balancers.get(target.name()) {
+                Some(balancer) if candidates.len() > 1 => balancer.pick(&candidates, player),
+                _ => candidates[0],
+            };
+
+            let slot = self.connections.slot(&server.name);
+            let init = tokio::time::timeout(connect_timeout, ProxyConnection::init(remote_addr, server, slot));
+            match init.await.unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out"))) {
+                Ok(connection) => return Ok(connection),
+                Err(e) => warn!("Could not connect to {} ({}): {}", server.name, server.address, e),
+            }
+
+            candidates.retain(|candidate| candidate.name != server.name);
+        }
+
+        Err(anyhow::anyhow!("No downstream of {} is reachable", target.name()))
+    }
+
+    pub fn online_players(&self) -> usize {
+        self.players.lock().unwrap().len()
+    }
+
+    pub fn player(&self, username: &str) -> Option<PlayerHandle> {
+        self.players.lock().unwrap().get(&username.to_lowercase()).cloned()
+    }
+
+    pub fn player_names(&self) -> Vec<String> {
+        self.players.lock().unwrap().values().map(|player| player.username.clone()).collect()
+    }
+
+    pub fn register_player(&self, player: PlayerHandle) {
+        self.players.lock().unwrap().insert(player.username.to_lowercase(), player);
+    }
+
+    /// Forget the player, unless another connection with the same name replaced it meanwhile.
+    pub fn unregister_player(&self, player: &PlayerHandle) {
+        let mut players = self.players.lock().unwrap();
+        let key = player.username.to_lowercase();
+
+        if players.get(&key).is_some_and(|registered| registered.is_same(player)) {
+            players.remove(&key);
+        }
+    }
+}
 
 pub struct ProxyServer {
-    config: Configuration
+    context: Arc<ProxyContext>,
 }
 
 impl ProxyServer {
-    pub fn new(config: Configuration) -> Self {
-        Self {
-            config
+    pub fn new(config: Configuration) -> anyhow::Result<Self> {
+        let authenticator = if config.online_mode {
+            info!("Generating RSA keypair for online-mode");
+            Some(Authenticator {
+                keys: ProxyKeys::generate()?,
+                sessions: SessionService::new(&config.session_server),
+            })
+        } else {
+            None
+        };
+
+        let motd = Motd::from_config(&config.motd)?;
+
+        let connections = Arc::new(ConnectionCounts::new(&config.downstreams));
+
+        let mut balancers = HashMap::new();
+        for group in config.downstreams.iter().filter_map(|downstream| downstream.group.as_ref()) {
+            let group_config = config.groups.get(group).cloned().unwrap_or_default();
+            balancers.entry(group.clone())
+                .or_insert_with(|| balancer::from_config(&group_config, connections.clone()));
         }
+
+        let health = Arc::new(HealthChecker::new(&config.health_check, &config.downstreams));
+
+        let mut downstream_motds = HashMap::new();
+        for downstream in &config.downstreams {
+            if let Some(motd) = &downstream.motd {
+                downstream_motds.insert(downstream.name.clone(), Motd::from_config(motd)?);
+            }
+        }
+
+        Ok(Self {
+            context: Arc::new(ProxyContext {
+                config,
+                authenticator,
+                motd,
+                downstream_motds,
+                players: Mutex::new(HashMap::new()),
+                connections,
+                balancers,
+                health,
+            }),
+        })
     }
 
     pub async fn run(&self) -> anyhow::Result<()> {
-        let listener = TcpListener::bind(&self.config.bind_address).await?;
+        let listener = TcpListener::bind(&self.context.config.bind_address).await?;
+        tokio::spawn(run_console(self.context.clone()));
 
-        loop {
-            let (socket, addr) = listener.accept().await?;
+        if self.context.config.health_check.enabled {
+            tokio::spawn(self.context.health.clone().run());
+        }
 
-            let (r, w) = socket.into_split();
-            let upstream = Upstream(r, w, addr);
+        loop {
+            let (mut socket, mut addr) = listener.accept().await?;
 
-            // TODO: choose default downstream server
-            let destination = self.config.downstreams[0].address.clone().parse().unwrap();
+            let context = self.context.clone();
             tokio::spawn(async move {
-                let mut connection = ProxyConnection::init(upstream, destination).await;
-                connection.establish().await;
+                // Behind a load balancer the real client address comes first
+                if context.config.proxy_protocol.expects_header(addr.ip()) {
+                    match proxy_protocol::read_header(&mut socket).await {
+                        Ok(Some(client_addr)) => addr = client_addr,
+                        Ok(None) => {},
+                        Err(e) => {
+                            error!("Error reading PROXY protocol header from {}: {}", addr, e);
+                            return;
+                        },
+                    }
+                }
+
+                // Pre-1.7 pings don't follow the usual framing, so check for them first
+                match handle_legacy_ping(&mut socket, &context).await {
+                    Ok(true) => return,
+                    Ok(false) => {},
+                    Err(e) => {
+                        error!("Error handling legacy ping from {}: {}", addr, e);
+                        return;
+                    },
+                }
+
+                let (r, w) = socket.into_split();
+                let upstream = Upstream(EncryptedReader::new(r), EncryptedWriter::new(w), addr);
+
+                if let Err(e) = InitialHandler::new(upstream, context).handle().await {
+                    error!("Error handling connection from {}: {}", addr, e);
+                }
             });
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,81 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use protocol::{chat::ChatComponent, packets::s2c::{ServerStatus, StatusVersion, StatusPlayers}};
+use serde_json::Map;
+
+use crate::config::MotdConfig;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Server list ping answers, so the proxy can reply without asking any downstream.
+pub struct Motd {
+    description: ChatComponent,
+    max_players: i32,
+    version_name: String,
+    favicon: Option<String>,
+}
+
+impl Motd {
+    pub fn from_config(config: &MotdConfig) -> anyhow::Result<Self> {
+        let favicon = match &config.favicon {
+            Some(path) => Some(load_favicon(path)?),
+            None => None,
+        };
+
+        Ok(Self {
+            description: ChatComponent::text(&config.description),
+            max_players: config.max_players,
+            version_name: config.version_name.clone(),
+            favicon,
+        })
+    }
+
+    pub fn description(&self) -> &ChatComponent {
+        &self.description
+    }
+
+    pub fn max_players(&self) -> i32 {
+        self.max_players
+    }
+
+    pub fn version_name(&self) -> &str {
+        &self.version_name
+    }
+
+    /// Build the status response. The client's own protocol version is reported back
+    /// since the proxy doesn't know which versions the downstreams accept.
+    pub fn status(&self, protocol_version: i32, online_players: usize) -> ServerStatus {
+        ServerStatus {
+            version: StatusVersion {
+                name: self.version_name.clone(),
+                protocol: protocol_version,
+            },
+            players: Some(StatusPlayers {
+                max: self.max_players,
+                online: online_players as i32,
+                sample: vec![],
+            }),
+            description: self.description.clone(),
+            favicon: self.favicon.clone(),
+            other: Map::new(),
+        }
+    }
+}
+
+/// Read a PNG favicon and encode it the way the status response expects.
+fn load_favicon(path: &str) -> anyhow::Result<String> {
+    let data = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read favicon '{}': {}", path, e))?;
+
+    if data.len() < 24 || !data.starts_with(PNG_SIGNATURE) {
+        return Err(anyhow::anyhow!("Favicon '{}' is not a PNG image", path));
+    }
+
+    // Width and height are the first fields of the IHDR chunk
+    let width = u32::from_be_bytes(data[16..20].try_into()?);
+    let height = u32::from_be_bytes(data[20..24].try_into()?);
+    if width != 64 || height != 64 {
+        return Err(anyhow::anyhow!("Favicon '{}' must be 64x64, got {}x{}", path, width, height));
+    }
+
+    Ok(format!("data:image/png;base64,{}", STANDARD.encode(data)))
+}
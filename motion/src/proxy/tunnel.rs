@@ -1,10 +1,32 @@
-use std::{sync::Arc, net::SocketAddr};
+use std::{sync::{Arc, atomic::{AtomicBool, AtomicI64, Ordering}}, net::SocketAddr, time::Duration};
 
-use protocol::{State, DirectionEnum, PacketReadExt, error::ProtocolError, packets::{Packet, C2SPacket, c2s::NextState, S2CPacket}, GameStateEnum, PacketWriteExt, uuid::UUID3};
-use tokio::{sync::Mutex, net::tcp::{OwnedWriteHalf, OwnedReadHalf}, io::AsyncWriteExt};
+use protocol::{
+    State, DirectionEnum, Decoded, decode_frame, error::ProtocolError, GameStateEnum, PacketWriteExt,
+    packets::{Packet, C2SPacket, c2s::{self, NextState}, S2CPacket, s2c::{self, Property}, registry::{self, PacketKind}},
+    uuid::UUID3, utils::read_varint_slice, compression::decompress_frame, codec::FrameCodec,
+};
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use tokio::{
+    sync::{mpsc, watch}, io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf}, task::JoinHandle,
+};
+use tokio_util::codec::FramedRead;
 
+use protocol::chat::ChatComponent;
+
+use super::{
+    auth::GameProfile, connection::ProxyConnection, player::SwitchRequest,
+    balancer::{ConnectionSlot, PlayerInfo}, server::ProxyContext,
+    forwarding::{VELOCITY_PLAYER_INFO_CHANNEL, BUNGEEGUARD_TOKEN_PROPERTY, velocity_player_info, legacy_server_address},
+};
+use crate::config::{DownstreamConfig, ForwardingMode};
+
+#[derive(Clone)]
 pub struct TunnelPipe {
     upstream_addr: SocketAddr,
+    context: Arc<ProxyContext>,
+    /// State of the client connection
     state: State,
     tunnel_state: TunnelState,
 }
@@ -12,143 +34,782 @@ pub struct TunnelPipe {
 #[derive(Debug, Clone, Default)]
 pub struct TunnelState {
     username: Option<String>,
+    /// Replayed to every downstream the player is sent to
+    login_start: Option<c2s::LoginStart>,
     waiting_login_start: bool,
+    /// Authenticated profile, only in online-mode
+    profile: Option<GameProfile>,
+    profile_uuid: Option<UUID3>,
 }
 
 impl TunnelPipe {
-    pub fn new(upstream_addr: SocketAddr) -> Self {
+    pub fn new(upstream_addr: SocketAddr, context: Arc<ProxyContext>) -> Self {
+        let state = State { max_frame_size: context.config.max_frame_size, ..Default::default() };
+
         Self {
             upstream_addr,
-            state: State::default(),
+            context,
+            state,
             tunnel_state: TunnelState::default(),
         }
     }
 
-    pub async fn establish_pipes(
-        &mut self, 
-        upstream: &mut (OwnedReadHalf, OwnedWriteHalf), 
-        downstream: &mut (OwnedReadHalf, OwnedWriteHalf)
-    ) {
-        let arc = Arc::new(Mutex::new(self));
+    pub fn state(&self) -> &State {
+        &self.state
+    }
 
-        let a = pipe(arc.clone(), &mut upstream.0, &mut downstream.1, DirectionEnum::C2S);
-        let b = pipe(arc, &mut downstream.0, &mut upstream.1, DirectionEnum::S2C);
+    /// State a fresh downstream connection is in while logging in.
+    pub fn login_state(&self) -> State {
+        State {
+            handshake: self.state.handshake.clone(),
+            state: GameStateEnum::Login,
+            compression_threshold: None,
+            max_frame_size: self.state.max_frame_size,
+        }
+    }
 
-        let _ = tokio::join!(a, b);
+    pub fn login_start(&self) -> Option<&c2s::LoginStart> {
+        self.tunnel_state.login_start.as_ref()
     }
 
-    pub fn transform_packet(&self, packet: &mut Packet) -> anyhow::Result<()> {
-        match packet {
-            Packet::C2S(packet) => {
-                match packet {
-                    C2SPacket::Handshake(packet) => {
-                        let uuid = UUID3::new(
-                            "OfflinePlayer:".to_string() + 
-                            &self.tunnel_state.username.as_ref().unwrap()
-                        );
-
-                        packet.server_address = [
-                            packet.server_address.clone(), 
-                            self.upstream_addr.ip().to_string(),
-                            uuid.to_string(),
-                        ].join("\x00");
-                    },
-                    _ => {}
-                }
+    pub fn set_profile(&mut self, profile: GameProfile) -> anyhow::Result<()> {
+        self.tunnel_state.profile_uuid = Some(profile.uuid()?);
+        self.tunnel_state.profile = Some(profile);
+
+        Ok(())
+    }
+
+    /// UUID of the player, the offline one if the player wasn't authenticated.
+    pub fn player_uuid(&self) -> anyhow::Result<UUID3> {
+        if let Some(uuid) = self.tunnel_state.profile_uuid {
+            return Ok(uuid);
+        }
+
+        let username = self.tunnel_state.username.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Login start packet not received"))?;
+
+        Ok(UUID3::new("OfflinePlayer:".to_string() + username))
+    }
+
+    fn username(&self) -> String {
+        self.tunnel_state.username.clone().unwrap_or_default()
+    }
+
+    /// Proxy packets between the client and the downstream until either of them disconnects.
+    /// The player is moved to another downstream on every [`SwitchRequest`].
+    ///
+    /// Each direction owns its reader and writer, the C2S one writing to the downstream and
+    /// the S2C one to the client. State transitions are published over a watch channel,
+    /// so no lock is taken for a packet.
+    pub async fn establish_pipes<UR, UW>(
+        self,
+        upstream: (&mut UR, &mut UW),
+        connection: ProxyConnection,
+        switches: &mut mpsc::Receiver<SwitchRequest>,
+    ) where
+        UR: AsyncRead + Unpin + Send, UW: AsyncWrite + Unpin + Send,
+    {
+        let (reader, writer) = connection.downstream;
+        let frames = DownstreamFrames::spawn(reader, connection.slot, self.state.max_frame_size);
+        let compression = self.state.compression_threshold;
+        let (states, _) = watch::channel(self.state.clone());
+        let shared = Arc::new(Shared { tunnel: self, states, keep_alive: PendingKeepAlive::default() });
+        let (commands, command_receiver) = mpsc::channel(16);
+
+        let c2s = StateView::new(&shared.states, compression);
+        let s2c = StateView::new(&shared.states, compression);
+
+        let a = pipe_c2s(shared.clone(), c2s, upstream.0, writer, command_receiver);
+        let b = pipe_s2c(shared, s2c, connection.server.name, frames, upstream.1, commands, switches);
+
+        // Once either side is gone there is nothing left to proxy
+        tokio::select! {
+            _ = a => {},
+            _ = b => {},
+        }
+    }
+
+    /// Answer a login plugin request of the downstream if it's meant for the proxy,
+    /// `None` if it's up to the client.
+    pub async fn answer_login_plugin(
+        &self,
+        request: &s2c::LoginPluginRequest,
+        downstream: &DownstreamConfig,
+    ) -> anyhow::Result<Option<c2s::LoginPluginResponse>> {
+        let secret = match &self.context.config.forwarding_secret {
+            Some(secret) if downstream.forwarding == ForwardingMode::Modern
+                && request.channel == VELOCITY_PLAYER_INFO_CHANNEL => secret,
+            _ => return Ok(None),
+        };
+
+        let (username, properties) = self.forwarded_profile();
+        let data = velocity_player_info(
+            secret, self.upstream_addr.ip(), self.player_uuid()?, &username, &properties,
+        ).await?;
+
+        Ok(Some(c2s::LoginPluginResponse { message_id: request.message_id, data: Some(data) }))
+    }
+
+    /// Forward the player info to the downstream the legacy (BungeeCord) way,
+    /// by packing it into the server address of the handshake.
+    pub fn forward_handshake(&self, handshake: &mut c2s::Handshake, downstream: &DownstreamConfig) -> anyhow::Result<()> {
+        let (_, mut properties) = self.forwarded_profile();
+
+        match (downstream.forwarding, &downstream.bungeeguard_token) {
+            (ForwardingMode::Legacy, _) => {},
+            (ForwardingMode::Bungeeguard, Some(token)) => {
+                properties.push(Property {
+                    name: BUNGEEGUARD_TOKEN_PROPERTY.to_string(),
+                    value: token.clone(),
+                    signature: None,
+                });
             },
-            _ => {}
+            (ForwardingMode::Bungeeguard, None) => {
+                return Err(anyhow::anyhow!("No BungeeGuard token set for {}", downstream.name));
+            },
+            // Modern forwarding sends the player info once the downstream asks for it
+            (ForwardingMode::None | ForwardingMode::Modern, _) => return Ok(()),
         }
 
+        handshake.server_address = legacy_server_address(
+            &handshake.server_address, self.upstream_addr.ip(), self.player_uuid()?, &properties,
+        )?;
+
         Ok(())
     }
 
-    pub fn update_state(&mut self, packet: &Packet) {
-        // let old_state = self.state.clone();
-
-        match packet {
-            Packet::C2S(packet) => {
-                match (packet, self.state.state) {
-                    (C2SPacket::Handshake(packet), GameStateEnum::Handshake) => {
-                        self.state.state = match packet.next_state {
-                            NextState::Login => GameStateEnum::Login,
-                            NextState::Status => GameStateEnum::Status,
-                        };
-                        self.state.handshake = Some(packet.clone());
-                        self.tunnel_state.waiting_login_start = true;
-                    },
-                    (C2SPacket::LoginStart(packet), GameStateEnum::Login) => {
-                        self.tunnel_state.username = Some(packet.username.clone());
-                    },
-                    _ => {}
+    /// Username and properties (skin textures) downstreams are told the player has.
+    fn forwarded_profile(&self) -> (String, Vec<Property>) {
+        match &self.tunnel_state.profile {
+            Some(profile) => (profile.name.clone(), profile.login_properties()),
+            None => (self.username(), vec![]),
+        }
+    }
+
+    pub fn transform_packet(&self, packet: &mut Packet) -> anyhow::Result<()> {
+        if let Packet::S2C(S2CPacket::LoginSuccess(packet)) = packet {
+            // Downstream servers run in offline mode, so tell the client who it really is
+            if let Some(profile) = &self.tunnel_state.profile {
+                packet.set_uuid(self.player_uuid()?);
+                packet.username = profile.name.clone();
+
+                if packet.properties.is_some() {
+                    packet.properties = Some(profile.login_properties());
                 }
             }
-            Packet::S2C(packet) => {
-                match (packet, self.state.state) {
-                    (S2CPacket::LoginSuccess(_packet), GameStateEnum::Login) => {
-                        self.state.state = GameStateEnum::Play;
-                    },
-                    _ => {}
-                }
+        }
+
+        Ok(())
+    }
+
+    pub fn update_state(&mut self, packet: &Packet) {
+        match (packet, self.state.state) {
+            (Packet::C2S(C2SPacket::Handshake(packet)), GameStateEnum::Handshake) => {
+                self.state.state = match packet.next_state {
+                    NextState::Login => GameStateEnum::Login,
+                    NextState::Status => GameStateEnum::Status,
+                };
+                self.state.handshake = Some(packet.clone());
+                self.tunnel_state.waiting_login_start = true;
+            },
+            (Packet::C2S(C2SPacket::LoginStart(packet)), GameStateEnum::Login) => {
+                self.tunnel_state.username = Some(packet.username.clone());
+                self.tunnel_state.login_start = Some(packet.clone());
+            },
+            _ => {
+                transition(&mut self.state, packet);
             },
         }
     }
 }
 
-async fn pipe(
-    tunnel: Arc<Mutex<&mut TunnelPipe>>, 
-    reader: &mut OwnedReadHalf, 
-    writer: &mut OwnedWriteHalf, 
-    direction: DirectionEnum
-) -> anyhow::Result<()> {
-    let dir_str: &str = direction.into();
-    loop {
-        let t = tunnel.lock().await;
-        if t.state.state == GameStateEnum::Handshake && direction == DirectionEnum::S2C {
-            continue;
+/// Move the client connection on to the state the packet leads to, `true` if it changed anything.
+/// These are the only changes to the state once the pipes are established.
+fn transition(state: &mut State, packet: &Packet) -> bool {
+    match packet {
+        Packet::C2S(packet) => {
+            match (packet, state.state) {
+                (C2SPacket::LoginAcknowledged(_), GameStateEnum::Login) => {
+                    state.state = GameStateEnum::Configuration;
+                },
+                (C2SPacket::AcknowledgeFinishConfiguration(_), GameStateEnum::Configuration) => {
+                    state.state = GameStateEnum::Play;
+                },
+                (C2SPacket::AcknowledgeConfiguration(_), GameStateEnum::Play) => {
+                    state.state = GameStateEnum::Configuration;
+                },
+                _ => return false,
+            }
         }
-        let state = t.state.clone();
-        drop(t);
-
-        let packet = reader.read_packet(&state, direction).await;
-
-        if let Err(e) = packet {
-            match e {
-                ProtocolError::UnknownPacketId { packet_id: _, data } => {
-                    writer.write_all(&data).await?;
-                    continue;
+        Packet::S2C(packet) => {
+            match (packet, state.state) {
+                // Since 1.20.2 the client acknowledges the login and gets configured first
+                (S2CPacket::LoginSuccess(_packet), GameStateEnum::Login) if !state.has_configuration_state() => {
+                    state.state = GameStateEnum::Play;
                 },
-                ProtocolError::ReadPacket { source } => {
-                    println!("Error reading packet from {}: {}", dir_str, source);
-                    break;
+                (S2CPacket::SetCompression(packet), GameStateEnum::Login) => {
+                    state.compression_threshold = usize::try_from(packet.threshold).ok();
                 },
+                _ => return false,
             }
+        },
+    }
+
+    true
+}
+
+/// What both directions of an established tunnel share, none of it behind a lock.
+struct Shared {
+    /// Only read from once the pipes are established, its state is left behind
+    tunnel: TunnelPipe,
+    /// Latest state of the client connection, published by the direction that saw the transition
+    states: watch::Sender<State>,
+    keep_alive: PendingKeepAlive,
+}
+
+/// One direction's copy of the client connection state. It's brought up to date with what
+/// the other direction published before every frame, which costs an atomic load when nothing changed.
+struct StateView {
+    updates: watch::Receiver<State>,
+    client: State,
+    /// Same as `client`, with the compression of the current downstream
+    downstream: State,
+    downstream_compression: Option<usize>,
+}
+
+impl StateView {
+    fn new(states: &watch::Sender<State>, downstream_compression: Option<usize>) -> Self {
+        let updates = states.subscribe();
+        let client = updates.borrow().clone();
+
+        Self {
+            downstream: State { compression_threshold: downstream_compression, ..client.clone() },
+            updates,
+            client,
+            downstream_compression,
         }
+    }
 
-        let mut packet = packet.unwrap();
-        if let Packet::C2S(C2SPacket::Handshake(_)) = packet {
-            tunnel.lock().await.update_state(&packet);
-            continue;
+    fn client(&self) -> &State {
+        &self.client
+    }
+
+    fn downstream(&self) -> &State {
+        &self.downstream
+    }
+
+    /// Pick up the transitions the other direction published.
+    fn refresh(&mut self) {
+        if self.updates.has_changed().unwrap_or(false) {
+            self.update();
         }
+    }
 
-        {
-            let mut t = tunnel.lock().await;
-            t.transform_packet(&mut packet).unwrap();
-            t.update_state(&packet);
+    /// Wait for the other direction to move the client to `state`.
+    async fn wait_for(&mut self, state: GameStateEnum) -> anyhow::Result<()> {
+        while self.client.state != state {
+            self.updates.changed().await?;
+            self.update();
         }
 
-        if let Packet::C2S(C2SPacket::LoginStart(_)) = packet {
-            let t = tunnel.lock().await;
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        self.client = self.updates.borrow_and_update().clone();
+        self.downstream = State { compression_threshold: self.downstream_compression, ..self.client.clone() };
+    }
+
+    /// Apply the transition the packet causes, if any, and publish it to the other direction.
+    fn transition(&mut self, states: &watch::Sender<State>, packet: &Packet) {
+        if !transition(&mut self.client, packet) {
+            return;
+        }
+
+        // Applied to the published state too, rather than replacing it, so a transition
+        // the other direction published in the meantime isn't lost
+        states.send_modify(|state| {
+            transition(state, packet);
+        });
+        self.refresh();
+    }
+
+    fn set_downstream_compression(&mut self, compression: Option<usize>) {
+        self.downstream_compression = compression;
+        self.downstream.compression_threshold = compression;
+    }
+}
+
+/// Keep alive of the current downstream the client didn't answer yet.
+/// Sent on the S2C side and answered on the C2S one.
+#[derive(Default)]
+struct PendingKeepAlive {
+    id: AtomicI64,
+    pending: AtomicBool,
+}
+
+impl PendingKeepAlive {
+    fn sent(&self, id: i64) {
+        self.id.store(id, Ordering::Relaxed);
+        self.pending.store(true, Ordering::Release);
+    }
+
+    /// Whether the answer is for the pending keep alive, which then isn't pending anymore.
+    /// Answers meant for the previous downstream are dropped, vanilla servers kick players for unexpected ones.
+    fn answer(&self, id: i64) -> bool {
+        self.pending.load(Ordering::Acquire)
+            && self.id.load(Ordering::Relaxed) == id
+            && self.pending.swap(false, Ordering::AcqRel)
+    }
+
+    fn clear(&self) {
+        self.pending.store(false, Ordering::Release);
+    }
+}
+
+/// What the S2C direction needs done with the downstream writer, which the C2S direction owns.
+enum DownstreamCommand {
+    /// Answer the downstream, e.g. a login plugin request meant for the proxy
+    Write(Packet),
+    /// The downstream enabled compression while logging in
+    Compression(Option<usize>),
+    /// Switched to another downstream with its compression,
+    /// dropping the old half closes the connection to the previous one
+    Replace(OwnedWriteHalf, Option<usize>),
+    /// Like `Replace`, for a downstream waiting in the configuration state (1.20.2+).
+    /// The client's packets are dropped until it acknowledges going back there as well
+    Reconfigure(OwnedWriteHalf, Option<usize>),
+}
+
+/// How long a client has to acknowledge going back to the configuration state when switching servers
+const RECONFIGURATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a downstream has to log the player in when switching servers,
+/// nothing reaches the player in the meantime
+const SWITCH_LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Frames read from a downstream on a separate task,
+/// so switching servers never abandons a half read frame.
+struct DownstreamFrames {
+    frames: mpsc::Receiver<Result<Bytes, ProtocolError>>,
+    task: JoinHandle<()>,
+    /// Released together with the downstream
+    _slot: ConnectionSlot,
+}
+
+impl DownstreamFrames {
+    fn spawn(reader: OwnedReadHalf, slot: ConnectionSlot, max_frame_size: usize) -> Self {
+        let (sender, frames) = mpsc::channel(64);
+
+        let task = tokio::spawn(async move {
+            let mut reader = FramedRead::new(reader, FrameCodec::new(max_frame_size));
+
+            // The stream ends when the downstream closes the connection, dropping the sender
+            while let Some(frame) = reader.next().await {
+                let frame = frame.map(BytesMut::freeze);
+                let failed = frame.is_err();
+
+                if sender.send(frame).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Self { frames, task, _slot: slot }
+    }
+}
+
+impl Drop for DownstreamFrames {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
 
-            if let Some(handshake) = t.state.handshake.clone() {
-                let mut handshake: Packet = Packet::C2S(C2SPacket::Handshake(handshake));
-                t.transform_packet(&mut handshake).unwrap();
-                writer.write_packet(&handshake, &state).await.unwrap();
-            } else {
+async fn pipe_c2s<R>(
+    shared: Arc<Shared>,
+    mut view: StateView,
+    reader: &mut R,
+    mut downstream: OwnedWriteHalf,
+    mut commands: mpsc::Receiver<DownstreamCommand>,
+) -> anyhow::Result<()> where R: AsyncRead + Unpin + Send {
+    let mut frames = FramedRead::new(reader, FrameCodec::new(view.client().max_frame_size));
+    // Set while the client is sent back to the configuration state for a new downstream
+    let mut reconfiguring = false;
+
+    loop {
+        let frame = tokio::select! {
+            biased;
+            Some(command) = commands.recv() => {
+                view.refresh();
+
+                // A downstream that went away is dealt with by the S2C side, which might move
+                // the player to a fallback one, so failed writes only drop the packet
+                match command {
+                    DownstreamCommand::Write(packet) => {
+                        let _ = downstream.write_packet(&packet, view.downstream()).await;
+                    },
+                    DownstreamCommand::Compression(compression) => view.set_downstream_compression(compression),
+                    DownstreamCommand::Replace(writer, compression) => {
+                        downstream = writer;
+                        view.set_downstream_compression(compression);
+                    },
+                    DownstreamCommand::Reconfigure(writer, compression) => {
+                        downstream = writer;
+                        view.set_downstream_compression(compression);
+                        reconfiguring = true;
+                    },
+                }
+                continue;
+            },
+            frame = frames.next() => frame,
+        };
+
+        let frame = match frame {
+            Some(Ok(frame)) => frame.freeze(),
+            Some(Err(e)) => {
+                warn!("{} sent a broken frame: {}", shared.tunnel.username(), e.at(None, view.client().state));
                 break;
+            },
+            None => break,
+        };
+
+        view.refresh();
+
+        let mut packet = match decode_frame(frame, view.client(), DirectionEnum::C2S).await {
+            Ok(Decoded::Packet(packet)) => packet,
+            Ok(Decoded::Raw(raw)) => {
+                if !reconfiguring {
+                    let _ = forward_frame(&mut downstream, &raw.frame, view.client(), view.downstream()).await;
+                }
+                continue;
+            },
+            // Vanilla servers kick players for broken packets as well
+            Err(e) => {
+                warn!("{} sent a broken packet: {}", shared.tunnel.username(), e);
+                break;
+            },
+        };
+
+        if reconfiguring {
+            // Play packets are meant for the previous downstream, and the new one is
+            // in the configuration state already, so the acknowledgement is only for the proxy
+            if let Packet::C2S(C2SPacket::AcknowledgeConfiguration(_)) = &packet {
+                view.transition(&shared.states, &packet);
+                reconfiguring = false;
             }
+            continue;
+        }
+
+        if let Packet::C2S(C2SPacket::KeepAlive(keep_alive)) = &packet {
+            if !shared.keep_alive.answer(keep_alive.id) {
+                continue;
+            }
+        }
+
+        let write_state = view.downstream().clone();
+        shared.tunnel.transform_packet(&mut packet)?;
+        view.transition(&shared.states, &packet);
+
+        let _ = downstream.write_packet(&packet, &write_state).await;
+    }
+    Ok(())
+}
+
+async fn pipe_s2c<W>(
+    shared: Arc<Shared>,
+    mut view: StateView,
+    mut server: String,
+    mut frames: DownstreamFrames,
+    writer: &mut W,
+    downstream: mpsc::Sender<DownstreamCommand>,
+    switches: &mut mpsc::Receiver<SwitchRequest>,
+) -> anyhow::Result<()> where W: AsyncWrite + Unpin + Send {
+    // Set after switching servers until the new downstream sends Join Game
+    let mut switching = false;
+    // Whether the downstream kicked the player itself
+    let mut kicked = false;
+    // Shown to the player once they joined the fallback downstream
+    let mut notice: Option<ChatComponent> = None;
+
+    loop {
+        tokio::select! {
+            frame = frames.frames.recv() => {
+                view.refresh();
+
+                let frame = match frame {
+                    Some(Ok(frame)) => frame,
+                    end => {
+                        if let Some(Err(e)) = end {
+                            warn!("{} sent a broken frame: {}", server, e.at(None, view.downstream().state));
+                        }
+                        if kicked {
+                            break;
+                        }
+
+                        let reason = ChatComponent::text(shared.tunnel.context.config.messages.server_unavailable(&server));
+                        match fall_back(&shared, &mut view, writer, &downstream, &server).await? {
+                            Some((new_server, new_frames)) => {
+                                notice = Some(fallback_notice(&shared, &new_server, reason));
+                                server = new_server;
+                                frames = new_frames;
+                                switching = true;
+                                continue;
+                            },
+                            None => {
+                                kick(view.client(), writer, reason).await?;
+                                break;
+                            },
+                        }
+                    },
+                };
+
+                let mut packet = match decode_frame(frame, view.downstream(), DirectionEnum::S2C).await {
+                    Ok(Decoded::Packet(packet)) => packet,
+                    Ok(Decoded::Raw(raw)) => {
+                        forward_frame(writer, &raw.frame, view.downstream(), view.client()).await?;
+                        continue;
+                    },
+                    // Only this packet is lost, the player stays connected without it
+                    Err(e) if e.is_packet_error() => {
+                        warn!("Dropped a packet from {}: {}", server, e);
+                        continue;
+                    },
+                    Err(e) => {
+                        warn!("{} sent a broken frame: {}", server, e);
+                        break;
+                    },
+                };
+
+                let write_state = view.client().clone();
+
+                if let Packet::S2C(S2CPacket::JoinGame(join_game)) = &packet {
+                    if switching {
+                        switching = false;
+                        if write_state.has_configuration_state() {
+                            // The client dropped the previous world when it was configured again
+                            writer.write_packet(&packet, &write_state).await?;
+                        } else {
+                            write_dimension_swap(writer, join_game, &write_state).await?;
+                        }
+
+                        if let Some(content) = notice.take() {
+                            let system_chat = s2c::SystemChat { content, overlay: false };
+                            writer.write_packet(&Packet::S2C(S2CPacket::SystemChat(system_chat)), &write_state).await?;
+                        }
+                        continue;
+                    }
+                }
+
+                if let Packet::S2C(S2CPacket::LoginPluginRequest(request)) = &packet {
+                    let response = match shared.tunnel.context.config.downstream(&server) {
+                        Some(config) => shared.tunnel.answer_login_plugin(request, config).await?,
+                        None => None,
+                    };
+                    if let Some(response) = response {
+                        let response = Packet::C2S(C2SPacket::LoginPluginResponse(response));
+                        let _ = downstream.send(DownstreamCommand::Write(response)).await;
+                        continue;
+                    }
+                }
+
+                if let Packet::S2C(S2CPacket::Disconnect(disconnect)) = &packet {
+                    // Kicked while playing, try to keep the player on the proxy
+                    if let Some((new_server, new_frames)) = fall_back(&shared, &mut view, writer, &downstream, &server).await? {
+                        notice = Some(fallback_notice(&shared, &new_server, disconnect.reason.clone()));
+                        server = new_server;
+                        frames = new_frames;
+                        switching = true;
+                        continue;
+                    }
+                }
+
+                match &packet {
+                    Packet::S2C(
+                        S2CPacket::LoginDisconnect(_) | S2CPacket::ConfigurationDisconnect(_) | S2CPacket::Disconnect(_)
+                    ) => kicked = true,
+                    Packet::S2C(S2CPacket::KeepAlive(keep_alive)) => shared.keep_alive.sent(keep_alive.id),
+                    Packet::S2C(S2CPacket::SetCompression(set_compression)) if view.client().state == GameStateEnum::Login => {
+                        let compression = usize::try_from(set_compression.threshold).ok();
+                        view.set_downstream_compression(compression);
+                        let _ = downstream.send(DownstreamCommand::Compression(compression)).await;
+                    },
+                    _ => {},
+                }
+
+                shared.tunnel.transform_packet(&mut packet)?;
+                view.transition(&shared.states, &packet);
+
+                writer.write_packet(&packet, &write_state).await?;
+            },
+            Some(request) = switches.recv() => {
+                view.refresh();
+
+                let new_server = request.connection.server.name.clone();
+                let result = switch(&shared, &mut view, writer, &downstream, request.connection).await?
+                    .map(|new_frames| {
+                        frames = new_frames;
+                        switching = true;
+                    });
+
+                let username = shared.tunnel.username();
+                match &result {
+                    Ok(()) => {
+                        info!("{} switched to {}", username, new_server);
+                        server = new_server;
+                    },
+                    Err(e) => warn!("{} could not switch to {}: {}", username, new_server, e),
+                }
+
+                let _ = request.result.send(result);
+            },
         }
+    }
+    Ok(())
+}
 
-        writer.write_packet(&packet, &state).await.unwrap();
+/// Disconnect the client with the reason, in whatever state it is in.
+pub async fn kick<W>(state: &State, writer: &mut W, reason: ChatComponent) -> anyhow::Result<()>
+where W: AsyncWrite + Unpin + Send {
+    if let Some(disconnect) = S2CPacket::disconnect(state, reason) {
+        writer.write_packet(&Packet::S2C(disconnect), state).await?;
     }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Move a playing player to the first fallback downstream that takes them after losing `lost`.
+/// Returns the name of the new downstream and its frames, errors if the player got kicked.
+async fn fall_back<W>(
+    shared: &Shared,
+    view: &mut StateView,
+    writer: &mut W,
+    downstream: &mpsc::Sender<DownstreamCommand>,
+    lost: &str,
+) -> anyhow::Result<Option<(String, DownstreamFrames)>> where W: AsyncWrite + Unpin + Send {
+    if view.client().state != GameStateEnum::Play {
+        return Ok(None);
+    }
+
+    let context = &shared.tunnel.context;
+    let username = shared.tunnel.username();
+    let uuid = match shared.tunnel.player_uuid() {
+        Ok(uuid) => uuid,
+        Err(_) => return Ok(None),
+    };
+    let player = PlayerInfo { username: &username, uuid };
+
+    for target in context.config.fallback_targets(lost) {
+        let connection = match context.connect(&target, shared.tunnel.upstream_addr, &player).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("{} could not fall back to {}: {}", username, target.name(), e);
+                continue;
+            },
+        };
+
+        let server = connection.server.name.clone();
+        match switch(shared, view, writer, downstream, connection).await? {
+            Ok(frames) => {
+                info!("{} lost {} and was moved to {}", username, lost, server);
+                return Ok(Some((server, frames)));
+            },
+            Err(e) => warn!("{} could not fall back to {}: {}", username, server, e),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Chat message telling the player why they ended up on the fallback downstream.
+fn fallback_notice(shared: &Shared, server: &str, reason: ChatComponent) -> ChatComponent {
+    let text = shared.tunnel.context.config.messages.moved_to_fallback(server);
+
+    ChatComponent { text, extra: vec![reason], ..Default::default() }
+}
+
+/// Log in to the new downstream and hand its writer over to the C2S direction.
+/// Before 1.20.2 the client stays in the play state the whole time, since then it's
+/// sent back to the configuration state, where the new downstream picks up.
+/// Versions before 1.19 can't be switched.
+/// The inner error leaves the player on the previous downstream, the outer one means
+/// the session is over, since a client sent to the configuration state can't go back.
+async fn switch<W>(
+    shared: &Shared,
+    view: &mut StateView,
+    writer: &mut W,
+    downstream: &mpsc::Sender<DownstreamCommand>,
+    mut connection: ProxyConnection,
+) -> anyhow::Result<anyhow::Result<DownstreamFrames>> where W: AsyncWrite + Unpin + Send {
+    if view.client().state != GameStateEnum::Play {
+        return Ok(Err(anyhow::anyhow!("Players can only be switched while playing")));
+    }
+    let protocol_version = view.client().protocol_version().unwrap_or_default();
+    let can_switch = [PacketKind::JoinGame, PacketKind::Respawn].into_iter()
+        .all(|kind| registry::packet_id(protocol_version, DirectionEnum::S2C, kind).is_some());
+    if !can_switch {
+        return Ok(Err(anyhow::anyhow!("Server switching is not supported for protocol version {}", protocol_version)));
+    }
+
+    let compression = match tokio::time::timeout(SWITCH_LOGIN_TIMEOUT, connection.login(&shared.tunnel)).await {
+        Ok(Ok(compression)) => compression,
+        Ok(Err(e)) => return Ok(Err(e)),
+        Err(_) => return Ok(Err(anyhow::anyhow!("Timed out while logging in"))),
+    };
+    let (reader, downstream_writer) = connection.downstream;
+
+    if view.client().has_configuration_state() {
+        downstream.send(DownstreamCommand::Reconfigure(downstream_writer, compression)).await
+            .map_err(|_| anyhow::anyhow!("Client disconnected"))?;
+        view.set_downstream_compression(compression);
+
+        let start = Packet::S2C(S2CPacket::StartConfiguration(s2c::StartConfiguration));
+        writer.write_packet(&start, view.client()).await?;
+        let acknowledged = tokio::time::timeout(RECONFIGURATION_TIMEOUT, view.wait_for(GameStateEnum::Configuration)).await;
+        if !matches!(acknowledged, Ok(Ok(()))) {
+            // Not acknowledged yet means still in the play state
+            warn!("{} didn't acknowledge the configuration while switching servers", shared.tunnel.username());
+            kick(view.client(), writer, ChatComponent::text("Timed out")).await?;
+            return Err(anyhow::anyhow!("Client didn't acknowledge the configuration"));
+        }
+    } else {
+        downstream.send(DownstreamCommand::Replace(downstream_writer, compression)).await
+            .map_err(|_| anyhow::anyhow!("Client disconnected"))?;
+        view.set_downstream_compression(compression);
+    }
+    shared.keep_alive.clear();
+
+    Ok(Ok(DownstreamFrames::spawn(reader, connection.slot, view.client().max_frame_size)))
+}
+
+/// Send the Join Game of the new downstream, then respawn the player in another
+/// dimension and back, so the client drops everything it knew about the previous world.
+async fn write_dimension_swap<W>(writer: &mut W, join_game: &s2c::JoinGame, state: &State) -> anyhow::Result<()>
+where W: AsyncWrite + Unpin + Send {
+    let respawn = s2c::Respawn::from_join_game(join_game);
+
+    let mut temporary = respawn.clone();
+    temporary.dimension_name = join_game.dimension_names.iter()
+        .find(|name| **name != join_game.dimension_name)
+        .cloned()
+        .unwrap_or_else(|| "motion:switch".to_string());
+
+    writer.write_packet(&Packet::S2C(S2CPacket::JoinGame(join_game.clone())), state).await?;
+    writer.write_packet(&Packet::S2C(S2CPacket::Respawn(temporary)), state).await?;
+    writer.write_packet(&Packet::S2C(S2CPacket::Respawn(respawn)), state).await?;
+
+    Ok(())
+}
+
+/// Pass a frame the proxy doesn't understand through as is,
+/// unless both sides use a different compression threshold.
+async fn forward_frame<W>(writer: &mut W, frame: &[u8], read_state: &State, write_state: &State) -> anyhow::Result<()>
+where W: AsyncWrite + Unpin + Send {
+    if read_state.compression_threshold == write_state.compression_threshold {
+        writer.write_all(frame).await?;
+        return Ok(());
+    }
+
+    let (_, prefix_len) = read_varint_slice(frame)?;
+    let data = decompress_frame(&frame[prefix_len..], read_state.compression_threshold)?;
+
+    writer.write_frame(&data, write_state).await
+}
@@ -0,0 +1,334 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Expr, Field, Fields, GenericArgument,
+    PathArguments, Type,
+};
+
+/// Implements `ReadExactPacket` and `WriteExactPacket` for a struct, fields are read and written in order.
+///
+/// Field types have to implement `PacketField`, unless one of the attributes says how to encode them:
+/// - `#[varint]` - `i32` encoded as a VarInt
+/// - `#[json]` - any serde type, encoded as a JSON string
+/// - `#[optional]` - `Option<T>` prefixed with a bool telling if it's there
+/// - `#[length_prefixed]` - `Vec<T>` prefixed with its VarInt length
+/// - `#[rest]` - `Vec<u8>` taking everything up to the end of the packet
+/// - `#[since(version)]` / `#[until(version)]` - `Option<T>` only sent by these protocol versions (inclusive)
+/// - `#[max_length(n)]` - `String` of at most `n` characters, longer ones fail to read
+///
+/// Attributes stack, so `#[since(ProtocolVersion::V1_19)] #[length_prefixed]` is an `Option<Vec<T>>`.
+#[proc_macro_derive(Packet, attributes(varint, json, optional, length_prefixed, rest, since, until, max_length))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect(),
+            Fields::Unit => vec![],
+            Fields::Unnamed(_) => {
+                return Err(syn::Error::new(input.span(), "Packet can't be derived for tuple structs"));
+            },
+        },
+        _ => return Err(syn::Error::new(input.span(), "Packet can only be derived for structs")),
+    };
+
+    let mut idents = vec![];
+    let mut reads = vec![];
+    let mut writes = vec![];
+    let mut gated = false;
+
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let spec = FieldSpec::parse(field)?;
+        gated |= spec.is_gated();
+
+        reads.push(read_gated(&spec, &field.ty)?);
+        writes.push(write_gated(&spec, &field.ty, &ident.to_string())?);
+        idents.push(ident);
+    }
+
+    let (reader, writer) = if idents.is_empty() {
+        (quote!(_reader), quote!(_writer))
+    } else {
+        (quote!(mut reader), quote!(mut writer))
+    };
+    let (state, protocol_version) = if gated {
+        (quote!(state), quote!(let protocol_version = ::protocol::packets::protocol_version(state)?;))
+    } else {
+        (quote!(_state), quote!())
+    };
+    let (borrow_reader, borrow_writer) = if idents.is_empty() {
+        (quote!(), quote!())
+    } else {
+        (quote!(let reader = &mut reader;), quote!(let writer = &mut writer;))
+    };
+    let construct = match &input.data {
+        Data::Struct(data) if matches!(data.fields, Fields::Unit) => quote!(Self),
+        _ => quote!(Self { #(#idents),* }),
+    };
+
+    Ok(quote! {
+        #[::async_trait::async_trait]
+        impl ::protocol::packets::ReadExactPacket for #name {
+            async fn read_packet(
+                #reader: impl ::protocol::utils::DataReadExt + ::std::marker::Send,
+                #state: &::protocol::State
+            ) -> ::anyhow::Result<Self> where Self: Sized {
+                #borrow_reader
+                #protocol_version
+                #(let #idents = #reads;)*
+
+                Ok(#construct)
+            }
+        }
+
+        #[::async_trait::async_trait]
+        impl ::protocol::packets::WriteExactPacket for #name {
+            async fn write_packet(
+                &self,
+                #writer: impl ::protocol::utils::DataWriteExt + ::std::marker::Send,
+                #state: &::protocol::State
+            ) -> ::anyhow::Result<()> {
+                #borrow_writer
+                #protocol_version
+                #({
+                    let value = &self.#idents;
+                    #writes
+                })*
+
+                Ok(())
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct FieldSpec {
+    varint: bool,
+    json: bool,
+    optional: bool,
+    length_prefixed: bool,
+    rest: bool,
+    since: Option<Expr>,
+    until: Option<Expr>,
+    max_length: Option<Expr>,
+}
+
+impl FieldSpec {
+    fn parse(field: &Field) -> syn::Result<Self> {
+        let mut spec = Self::default();
+
+        for attr in &field.attrs {
+            let path = attr.path();
+            let flag = if path.is_ident("varint") {
+                &mut spec.varint
+            } else if path.is_ident("json") {
+                &mut spec.json
+            } else if path.is_ident("optional") {
+                &mut spec.optional
+            } else if path.is_ident("length_prefixed") {
+                &mut spec.length_prefixed
+            } else if path.is_ident("rest") {
+                &mut spec.rest
+            } else if path.is_ident("since") {
+                spec.since = Some(attr.parse_args()?);
+                continue;
+            } else if path.is_ident("until") {
+                spec.until = Some(attr.parse_args()?);
+                continue;
+            } else if path.is_ident("max_length") {
+                spec.max_length = Some(attr.parse_args()?);
+                continue;
+            } else {
+                continue;
+            };
+
+            attr.meta.require_path_only()?;
+            *flag = true;
+        }
+
+        if spec.varint && spec.json {
+            return Err(syn::Error::new(field.span(), "`#[varint]` and `#[json]` can't be combined"));
+        }
+        if spec.rest && (spec.length_prefixed || spec.varint || spec.json) {
+            return Err(syn::Error::new(field.span(), "`#[rest]` is always raw bytes"));
+        }
+        if spec.max_length.is_some() && (spec.rest || spec.varint || spec.json) {
+            return Err(syn::Error::new(field.span(), "`#[max_length]` only applies to strings"));
+        }
+
+        Ok(spec)
+    }
+
+    fn is_gated(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+
+    fn gate_condition(&self) -> TokenStream2 {
+        let mut conditions = vec![];
+        if let Some(since) = &self.since {
+            conditions.push(quote!(protocol_version >= ::protocol::version::ProtocolVersion::from(#since)));
+        }
+        if let Some(until) = &self.until {
+            conditions.push(quote!(protocol_version <= ::protocol::version::ProtocolVersion::from(#until)));
+        }
+
+        quote!(#(#conditions)&&*)
+    }
+}
+
+/// The `T` of `wrapper<T>`, e.g. `Option<T>` or `Vec<T>`.
+fn inner_type<'a>(ty: &'a Type, wrapper: &str) -> syn::Result<&'a Type> {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if let PathArguments::AngleBracketed(arguments) = &segment.arguments {
+                if let (true, Some(GenericArgument::Type(inner))) = (segment.ident == wrapper, arguments.args.first()) {
+                    return Ok(inner);
+                }
+            }
+        }
+    }
+
+    Err(syn::Error::new(ty.span(), format!("expected `{}<...>`", wrapper)))
+}
+
+fn is_byte(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident("u8"))
+}
+
+fn read_gated(spec: &FieldSpec, ty: &Type) -> syn::Result<TokenStream2> {
+    if !spec.is_gated() {
+        return read_optional(spec, ty);
+    }
+
+    let condition = spec.gate_condition();
+    let read = read_optional(spec, inner_type(ty, "Option")?)?;
+
+    Ok(quote!(if #condition { Some(#read) } else { None }))
+}
+
+fn read_optional(spec: &FieldSpec, ty: &Type) -> syn::Result<TokenStream2> {
+    if !spec.optional {
+        return read_sequence(spec, ty);
+    }
+
+    let read = read_sequence(spec, inner_type(ty, "Option")?)?;
+
+    Ok(quote! {
+        if ::protocol::utils::DataReadExt::read_bool(&mut *reader).await? { Some(#read) } else { None }
+    })
+}
+
+fn read_sequence(spec: &FieldSpec, ty: &Type) -> syn::Result<TokenStream2> {
+    if spec.rest {
+        return Ok(quote!(::protocol::packets::field::read_rest(&mut *reader).await?));
+    }
+    if !spec.length_prefixed {
+        return Ok(read_value(spec, ty));
+    }
+
+    let item = inner_type(ty, "Vec")?;
+    if is_byte(item) && !spec.varint && !spec.json {
+        return Ok(quote!(::protocol::utils::DataReadExt::read_byte_array(&mut *reader).await?));
+    }
+
+    let read = read_value(spec, item);
+
+    Ok(quote! {{
+        let length = ::protocol::packets::field::read_length(&mut *reader).await?;
+        let mut items = Vec::new();
+        for _ in 0..length {
+            items.push(#read);
+        }
+        items
+    }})
+}
+
+fn read_value(spec: &FieldSpec, ty: &Type) -> TokenStream2 {
+    if spec.varint {
+        quote!(::protocol::utils::DataReadExt::read_varint(&mut *reader).await?)
+    } else if let Some(max_length) = &spec.max_length {
+        quote!(::protocol::utils::DataReadExt::read_string_bounded(&mut *reader, #max_length).await?)
+    } else if spec.json {
+        quote!(::protocol::packets::field::read_json(&mut *reader).await?)
+    } else {
+        quote!(<#ty as ::protocol::packets::field::PacketField>::read_field(&mut *reader).await?)
+    }
+}
+
+// Writes expect the field (or the part of it being written) to be borrowed as `value`
+
+fn write_gated(spec: &FieldSpec, ty: &Type, name: &str) -> syn::Result<TokenStream2> {
+    if !spec.is_gated() {
+        return write_optional(spec, ty);
+    }
+
+    let condition = spec.gate_condition();
+    let write = write_optional(spec, inner_type(ty, "Option")?)?;
+
+    Ok(quote! {
+        if #condition {
+            match value {
+                Some(value) => { #write },
+                None => return Err(::protocol::packets::field::missing_field(#name, protocol_version)),
+            }
+        }
+    })
+}
+
+fn write_optional(spec: &FieldSpec, ty: &Type) -> syn::Result<TokenStream2> {
+    if !spec.optional {
+        return write_sequence(spec, ty);
+    }
+
+    let write = write_sequence(spec, inner_type(ty, "Option")?)?;
+
+    Ok(quote! {
+        ::protocol::utils::DataWriteExt::write_bool(&mut *writer, value.is_some()).await?;
+        if let Some(value) = value {
+            #write
+        }
+    })
+}
+
+fn write_sequence(spec: &FieldSpec, ty: &Type) -> syn::Result<TokenStream2> {
+    if spec.rest {
+        return Ok(quote!(::protocol::packets::field::write_rest(&mut *writer, value).await?;));
+    }
+    if !spec.length_prefixed {
+        return Ok(write_value(spec));
+    }
+
+    let item = inner_type(ty, "Vec")?;
+    if is_byte(item) && !spec.varint && !spec.json {
+        return Ok(quote!(::protocol::utils::DataWriteExt::write_byte_array(&mut *writer, value).await?;));
+    }
+
+    let write = write_value(spec);
+
+    Ok(quote! {
+        ::protocol::packets::field::write_length(&mut *writer, value.len()).await?;
+        for value in value.iter() {
+            #write
+        }
+    })
+}
+
+fn write_value(spec: &FieldSpec) -> TokenStream2 {
+    if spec.varint {
+        quote!(::protocol::utils::DataWriteExt::write_varint(&mut *writer, *value).await?;)
+    } else if spec.json {
+        quote!(::protocol::packets::field::write_json(&mut *writer, value).await?;)
+    } else {
+        quote!(::protocol::packets::field::PacketField::write_field(value, &mut *writer).await?;)
+    }
+}
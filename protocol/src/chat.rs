@@ -0,0 +1,63 @@
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+/// JSON chat component. Only `text` and `extra` are typed,
+/// everything else (colors, styles, translations) is kept as is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RawChatComponent")]
+pub struct ChatComponent {
+    #[serde(default)]
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<ChatComponent>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl ChatComponent {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self { text: text.into(), ..Default::default() }
+    }
+
+    /// Plain text of the component and all of its children.
+    pub fn to_plain_text(&self) -> String {
+        let mut text = self.text.clone();
+        for extra in &self.extra {
+            text.push_str(&extra.to_plain_text());
+        }
+
+        text
+    }
+}
+
+impl From<&str> for ChatComponent {
+    fn from(text: &str) -> Self {
+        Self::text(text)
+    }
+}
+
+/// Chat components can also be plain strings or arrays of components.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawChatComponent {
+    Text(String),
+    Array(Vec<ChatComponent>),
+    Component {
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        extra: Vec<ChatComponent>,
+        #[serde(flatten)]
+        other: Map<String, Value>,
+    },
+}
+
+impl From<RawChatComponent> for ChatComponent {
+    fn from(raw: RawChatComponent) -> Self {
+        match raw {
+            RawChatComponent::Text(text) => Self::text(text),
+            RawChatComponent::Array(extra) => Self { extra, ..Default::default() },
+            RawChatComponent::Component { text, extra, other } => Self { text, extra, other },
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{compression, error::{ProtocolError, PacketContext}};
+
+/// Longest VarInt the protocol allows
+const MAX_VARINT_LENGTH: usize = 5;
+
+/// Largest frame vanilla accepts, the most a 3 byte VarInt length prefix can hold
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 2_097_151;
+
+/// Reads a VarInt from the beginning of a slice, returning the value and the amount of bytes it took.
+/// `Ok(None)` means the VarInt isn't complete yet.
+pub fn decode_varint(data: &[u8]) -> Result<Option<(i32, usize)>, ProtocolError> {
+    let mut result = 0;
+
+    for (i, byte) in data.iter().take(MAX_VARINT_LENGTH).enumerate() {
+        result |= ((byte & 0x7f) as i32) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(Some((result, i + 1)));
+        }
+    }
+
+    if data.len() >= MAX_VARINT_LENGTH {
+        return Err(ProtocolError::VarIntTooLong { context: PacketContext::default() });
+    }
+
+    Ok(None)
+}
+
+/// Splits a byte stream into frames without doing any IO.
+/// Frames are returned whole, length prefix included, the same way [`crate::PacketReadExt::read_frame`] does.
+#[derive(Debug, Clone)]
+pub struct FrameDecoder {
+    /// Frames announcing a bigger length are rejected before they are buffered
+    pub max_frame_size: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    /// Length of the first frame in `data` (prefix included), `Ok(None)` if it isn't all there yet.
+    pub fn frame_length(&self, data: &[u8]) -> Result<Option<usize>, ProtocolError> {
+        let (length, prefix_len) = match decode_varint(data)? {
+            Some(varint) => varint,
+            None => return Ok(None),
+        };
+        let length = usize::try_from(length).map_err(|_| ProtocolError::NegativeLength { length, context: PacketContext::default() })?;
+        if length > self.max_frame_size {
+            return Err(ProtocolError::FrameTooLarge { length, max: self.max_frame_size, context: PacketContext::default() });
+        }
+
+        let frame_len = prefix_len + length;
+
+        Ok((data.len() >= frame_len).then_some(frame_len))
+    }
+
+    /// Take the first frame off the front of `buffer`, `Ok(None)` if more bytes are needed.
+    pub fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<BytesMut>, ProtocolError> {
+        let frame_len = match self.frame_length(buffer)? {
+            Some(frame_len) => frame_len,
+            None => return Ok(None),
+        };
+
+        Ok(Some(buffer.split_to(frame_len)))
+    }
+}
+
+/// Turns `packet id | data` into frames, compressing them once a threshold is set.
+#[derive(Debug, Clone, Default)]
+pub struct FrameEncoder {
+    pub compression_threshold: Option<usize>,
+}
+
+impl FrameEncoder {
+    pub fn new(compression_threshold: Option<usize>) -> Self {
+        Self { compression_threshold }
+    }
+
+    /// Append the frame of `data` to `buffer`.
+    pub fn encode(&self, data: &[u8], buffer: &mut BytesMut) -> anyhow::Result<()> {
+        let data = compression::compress_frame(data, self.compression_threshold)?;
+
+        let mut prefix = Vec::with_capacity(MAX_VARINT_LENGTH);
+        crate::utils::write_varint_vec(&mut prefix, data.len() as i32);
+
+        buffer.reserve(prefix.len() + data.len());
+        buffer.put_slice(&prefix);
+        buffer.put_slice(&data);
+
+        Ok(())
+    }
+}
+
+/// [`FrameDecoder`] and [`FrameEncoder`] for `tokio_util`'s `FramedRead`/`FramedWrite`.
+#[derive(Debug, Clone, Default)]
+pub struct FrameCodec {
+    pub decoder: FrameDecoder,
+    pub encoder: FrameEncoder,
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self { decoder: FrameDecoder::new(max_frame_size), encoder: FrameEncoder::default() }
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decoder.decode(buffer)
+    }
+}
+
+impl Encoder<&[u8]> for FrameCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, data: &[u8], buffer: &mut BytesMut) -> Result<(), Self::Error> {
+        Ok(self.encoder.encode(data, buffer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::{
+        compression::{compress_frame, decompress_frame, MAX_DECOMPRESSED_SIZE},
+        utils::{read_varint_slice, write_varint_vec},
+    };
+
+    fn varint(value: i32) -> Vec<u8> {
+        let mut data = vec![];
+        write_varint_vec(&mut data, value);
+        data
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, 127, 128, 255, 25565, 2_097_151, i32::MAX, -1, i32::MIN] {
+            let data = varint(value);
+            assert_eq!(decode_varint(&data).unwrap(), Some((value, data.len())));
+            assert_eq!(read_varint_slice(&data).unwrap(), (value, data.len()));
+        }
+    }
+
+    #[test]
+    fn varint_partial_input() {
+        let data = varint(2_097_151);
+        for end in 0..data.len() {
+            assert_eq!(decode_varint(&data[..end]).unwrap(), None);
+
+            let error = read_varint_slice(&data[..end]).unwrap_err();
+            assert!(matches!(error.downcast_ref(), Some(ProtocolError::UnexpectedEof { .. })));
+        }
+    }
+
+    #[test]
+    fn varint_too_long() {
+        let data = [0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert!(matches!(decode_varint(&data), Err(ProtocolError::VarIntTooLong { .. })));
+        // Known to be too long before the sixth byte arrives
+        assert!(matches!(decode_varint(&data[..5]), Err(ProtocolError::VarIntTooLong { .. })));
+
+        let error = read_varint_slice(&data).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ProtocolError::VarIntTooLong { .. })));
+    }
+
+    #[test]
+    fn frames_wait_for_partial_input() {
+        let mut decoder = FrameDecoder::default();
+        let mut frame = varint(3);
+        frame.extend_from_slice(&[0x00, 0x01, 0x02]);
+
+        for end in 0..frame.len() {
+            let mut buffer = BytesMut::from(&frame[..end]);
+            assert_eq!(decoder.decode(&mut buffer).unwrap(), None);
+            assert_eq!(buffer.len(), end);
+        }
+
+        // The second frame is left in the buffer
+        let mut buffer = BytesMut::from(&frame[..]);
+        buffer.extend_from_slice(&frame[..2]);
+        assert_eq!(decoder.decode(&mut buffer).unwrap().as_deref(), Some(&frame[..]));
+        assert_eq!(&buffer[..], &frame[..2]);
+        assert_eq!(decoder.decode(&mut buffer).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut decoder = FrameDecoder::new(16);
+
+        // Rejected from the length prefix alone, before the frame is buffered
+        let mut buffer = BytesMut::from(&varint(17)[..]);
+        assert!(matches!(
+            decoder.decode(&mut buffer),
+            Err(ProtocolError::FrameTooLarge { length: 17, max: 16, .. })
+        ));
+
+        let mut frame = varint(16);
+        frame.extend_from_slice(&[0; 16]);
+        assert_eq!(decoder.decode(&mut BytesMut::from(&frame[..])).unwrap().map(|frame| frame.len()), Some(17));
+
+        let mut buffer = BytesMut::from(&varint(-1)[..]);
+        assert!(matches!(decoder.decode(&mut buffer), Err(ProtocolError::NegativeLength { length: -1, .. })));
+
+        let mut buffer = BytesMut::from(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x00][..]);
+        assert!(matches!(decoder.decode(&mut buffer), Err(ProtocolError::VarIntTooLong { .. })));
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let small = vec![0x05, 1, 2, 3];
+        let large: Vec<u8> = (0..1024).map(|i| (i % 7) as u8).collect();
+
+        for threshold in [None, Some(0), Some(256)] {
+            for data in [&small, &large] {
+                let mut buffer = BytesMut::new();
+                FrameEncoder::new(threshold).encode(data, &mut buffer).unwrap();
+
+                let frame = FrameDecoder::default().decode(&mut buffer).unwrap().unwrap();
+                assert!(buffer.is_empty());
+
+                let (_, prefix_len) = read_varint_slice(&frame).unwrap();
+                assert_eq!(&decompress_frame(&frame[prefix_len..], threshold).unwrap(), data);
+            }
+        }
+
+        // Big packets actually get compressed
+        let mut buffer = BytesMut::new();
+        FrameEncoder::new(Some(256)).encode(&large, &mut buffer).unwrap();
+        assert!(buffer.len() < large.len());
+    }
+
+    #[test]
+    fn compressed_frames_are_bounded() {
+        // Claims to inflate to more than vanilla accepts
+        let mut frame = varint(MAX_DECOMPRESSED_SIZE as i32 + 1);
+        frame.extend_from_slice(&[0x78, 0x9c, 0x03, 0x00]);
+        let error = decompress_frame(&frame, Some(256)).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ProtocolError::FrameTooLarge { .. })));
+
+        // Below the threshold, so it shouldn't have been compressed
+        let mut frame = varint(16);
+        frame.extend_from_slice(&[0x78, 0x9c, 0x03, 0x00]);
+        assert!(decompress_frame(&frame, Some(256)).is_err());
+
+        // Inflates to less than it claims
+        let compressed = compress_frame_with_claim(&[0x01; 300], 400);
+        assert!(decompress_frame(&compressed, Some(256)).is_err());
+    }
+
+    fn compress_frame_with_claim(data: &[u8], claimed: i32) -> Vec<u8> {
+        let frame = compress_frame(data, Some(0)).unwrap();
+        let (_, read) = read_varint_slice(&frame).unwrap();
+
+        let mut claimed_frame = varint(claimed);
+        claimed_frame.extend_from_slice(&frame[read..]);
+        claimed_frame
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{utils::{read_varint_slice, write_varint_vec}, error::{ProtocolError, PacketContext}};
+
+/// Largest packet vanilla inflates, compressed frames claiming more are rejected before inflating
+pub const MAX_DECOMPRESSED_SIZE: usize = 8_388_608;
+
+/// Turns the contents of a frame (everything after the packet length prefix)
+/// into `packet id | data`, inflating it if compression is enabled.
+pub fn decompress_frame(frame: &[u8], threshold: Option<usize>) -> anyhow::Result<Vec<u8>> {
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => return Ok(frame.to_vec()),
+    };
+
+    let (data_length, read) = read_varint_slice(frame)?;
+    let compressed = &frame[read..];
+
+    // Data length of 0 means the packet was sent uncompressed
+    if data_length == 0 {
+        return Ok(compressed.to_vec());
+    }
+
+    if data_length < 0 || (data_length as usize) < threshold {
+        return Err(anyhow::anyhow!(
+            "Compressed packet is smaller than the threshold: {} < {}", data_length, threshold
+        ));
+    }
+    if data_length as usize > MAX_DECOMPRESSED_SIZE {
+        return Err(ProtocolError::FrameTooLarge { length: data_length as usize, max: MAX_DECOMPRESSED_SIZE, context: PacketContext::default() }.into());
+    }
+
+    let mut data = Vec::with_capacity(data_length as usize);
+    ZlibDecoder::new(compressed)
+        .take(data_length as u64 + 1)
+        .read_to_end(&mut data)?;
+
+    if data.len() != data_length as usize {
+        return Err(anyhow::anyhow!(
+            "Decompressed packet size mismatch: expected {}, got {}", data_length, data.len()
+        ));
+    }
+
+    Ok(data)
+}
+
+/// Turns `packet id | data` into the contents of a frame, compressing it
+/// if compression is enabled and the packet is large enough.
+pub fn compress_frame(data: &[u8], threshold: Option<usize>) -> anyhow::Result<Vec<u8>> {
+    let threshold = match threshold {
+        Some(threshold) => threshold,
+        None => return Ok(data.to_vec()),
+    };
+
+    let mut frame = vec![];
+
+    if data.len() < threshold {
+        write_varint_vec(&mut frame, 0);
+        frame.extend_from_slice(data);
+    } else {
+        write_varint_vec(&mut frame, data.len() as i32);
+
+        let mut encoder = ZlibEncoder::new(frame, Compression::default());
+        encoder.write_all(data)?;
+        frame = encoder.finish()?;
+    }
+
+    Ok(frame)
+}
+
+/// Packet id of a frame (everything after the packet length prefix),
+/// inflating only as much as the id takes when the packet is compressed.
+pub fn peek_packet_id(frame: &[u8], threshold: Option<usize>) -> anyhow::Result<i32> {
+    if threshold.is_none() {
+        return Ok(read_varint_slice(frame)?.0);
+    }
+
+    let (data_length, read) = read_varint_slice(frame)?;
+    let data = &frame[read..];
+
+    if data_length == 0 {
+        return Ok(read_varint_slice(data)?.0);
+    }
+
+    let mut head = Vec::with_capacity(5);
+    ZlibDecoder::new(data).take(5).read_to_end(&mut head)?;
+
+    Ok(read_varint_slice(&head)?.0)
+}
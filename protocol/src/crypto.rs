@@ -0,0 +1,139 @@
+use std::{pin::Pin, task::{Context, Poll, ready}, io};
+
+use aes::{Aes128, cipher::{KeyIvInit, BlockEncryptMut, BlockDecryptMut, inout::InOutBuf}};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub type Encryptor = cfb8::Encryptor<Aes128>;
+pub type Decryptor = cfb8::Decryptor<Aes128>;
+
+/// Minecraft uses the shared secret both as the key and as the IV.
+fn new_cipher<C: KeyIvInit>(shared_secret: &[u8]) -> anyhow::Result<C> {
+    C::new_from_slices(shared_secret, shared_secret)
+        .map_err(|_| anyhow::anyhow!("Invalid shared secret length: {}", shared_secret.len()))
+}
+
+fn encrypt(cipher: &mut Encryptor, data: &mut [u8]) {
+    let (blocks, _) = InOutBuf::from(data).into_chunks();
+    cipher.encrypt_blocks_inout_mut(blocks);
+}
+
+fn decrypt(cipher: &mut Decryptor, data: &mut [u8]) {
+    let (blocks, _) = InOutBuf::from(data).into_chunks();
+    cipher.decrypt_blocks_inout_mut(blocks);
+}
+
+/// Reader that decrypts everything read from the inner reader (AES/CFB8)
+/// once encryption was enabled. Until then it just passes data through.
+pub struct EncryptedReader<R> {
+    inner: R,
+    cipher: Option<Decryptor>,
+}
+
+impl<R> EncryptedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, cipher: None }
+    }
+
+    pub fn enable_encryption(&mut self, shared_secret: &[u8]) -> anyhow::Result<()> {
+        self.cipher = Some(new_cipher(shared_secret)?);
+
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for EncryptedReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        if let Some(cipher) = &mut this.cipher {
+            decrypt(cipher, &mut buf.filled_mut()[filled..]);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writer that encrypts everything written to the inner writer (AES/CFB8)
+/// once encryption was enabled. Until then it just passes data through.
+pub struct EncryptedWriter<W> {
+    inner: W,
+    cipher: Option<Encryptor>,
+    buffer: Vec<u8>,
+}
+
+impl<W> EncryptedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, cipher: None, buffer: vec![] }
+    }
+
+    pub fn enable_encryption(&mut self, shared_secret: &[u8]) -> anyhow::Result<()> {
+        self.cipher = Some(new_cipher(shared_secret)?);
+
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptedWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let cipher = match &mut this.cipher {
+            Some(cipher) => cipher,
+            None => return Pin::new(&mut this.inner).poll_write(cx, buf),
+        };
+
+        // The cipher state depends on every byte encrypted before, so it can
+        // only be advanced by the amount of bytes the inner writer accepted.
+        let mut next = cipher.clone();
+        this.buffer.clear();
+        this.buffer.extend_from_slice(buf);
+        encrypt(&mut next, &mut this.buffer);
+
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.buffer))?;
+
+        if written == buf.len() {
+            *cipher = next;
+        } else {
+            this.buffer.clear();
+            this.buffer.extend_from_slice(&buf[..written]);
+            encrypt(cipher, &mut this.buffer);
+        }
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -1,16 +1,140 @@
+use std::fmt;
+
 use snafu::Snafu;
 
+use crate::GameStateEnum;
+
+/// Which packet an error is about, as far as the reader got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketContext {
+    /// `None` if the error happened before the packet id was read, i.e. the frame itself is broken
+    pub packet_id: Option<i32>,
+    /// `None` if the reader doesn't know the state, like [`crate::codec::FrameDecoder`]
+    pub state: Option<GameStateEnum>,
+}
+
+impl fmt::Display for PacketContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.packet_id {
+            Some(packet_id) => write!(f, "packet {:#04x}", packet_id)?,
+            None => write!(f, "frame")?,
+        }
+        if let Some(state) = self.state {
+            write!(f, " in {:?} state", state)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Snafu)]
 pub enum ProtocolError {
-    #[snafu(display("Unknown packet id: {}", packet_id))]
-    UnknownPacketId { packet_id: i32, data: Vec<u8> },
+    /// Only an error where the proxy has to understand every packet, unknown ones are passed through otherwise
+    #[snafu(display("Unknown {}", context))]
+    UnknownPacketId { context: PacketContext, data: Vec<u8> },
+
+    #[snafu(display("Unexpected end of {}", context))]
+    UnexpectedEof { context: PacketContext },
+
+    #[snafu(display("VarInt is too long in {}", context))]
+    VarIntTooLong { context: PacketContext },
+
+    #[snafu(display("Frame of {} bytes is bigger than the maximum of {} ({})", length, max, context))]
+    FrameTooLarge { length: usize, max: usize, context: PacketContext },
+
+    #[snafu(display("String is longer than the maximum of {} characters in {}", max, context))]
+    StringTooLong { max: usize, context: PacketContext },
+
+    #[snafu(display("Negative length {} in {}", length, context))]
+    NegativeLength { length: i32, context: PacketContext },
+
+    #[snafu(display("Invalid UTF-8 in {}", context))]
+    InvalidUtf8 { context: PacketContext },
+
+    #[snafu(display("Invalid {} {} in {}", name, value, context))]
+    InvalidEnumValue { name: &'static str, value: i32, context: PacketContext },
+
+    #[snafu(display("Unexpected {}", context))]
+    WrongState { context: PacketContext },
 
-    #[snafu(display("Failed to read packet: {}", source))]
-    ReadPacket { source: anyhow::Error },
+    #[snafu(display("{} bytes left over after reading {}", length, context))]
+    TrailingBytes { length: usize, context: PacketContext },
+
+    #[snafu(display("Failed to read {}: {}", context, source))]
+    ReadPacket { source: anyhow::Error, context: PacketContext },
+}
+
+impl ProtocolError {
+    pub fn context(&self) -> &PacketContext {
+        match self {
+            ProtocolError::UnknownPacketId { context, .. }
+            | ProtocolError::UnexpectedEof { context }
+            | ProtocolError::VarIntTooLong { context }
+            | ProtocolError::FrameTooLarge { context, .. }
+            | ProtocolError::StringTooLong { context, .. }
+            | ProtocolError::NegativeLength { context, .. }
+            | ProtocolError::InvalidUtf8 { context }
+            | ProtocolError::InvalidEnumValue { context, .. }
+            | ProtocolError::WrongState { context }
+            | ProtocolError::TrailingBytes { context, .. }
+            | ProtocolError::ReadPacket { context, .. } => context,
+        }
+    }
+
+    /// Fill in what the error doesn't know yet about the packet.
+    pub fn at(mut self, packet_id: Option<i32>, state: GameStateEnum) -> Self {
+        let context = match &mut self {
+            ProtocolError::UnknownPacketId { context, .. }
+            | ProtocolError::UnexpectedEof { context }
+            | ProtocolError::VarIntTooLong { context }
+            | ProtocolError::FrameTooLarge { context, .. }
+            | ProtocolError::StringTooLong { context, .. }
+            | ProtocolError::NegativeLength { context, .. }
+            | ProtocolError::InvalidUtf8 { context }
+            | ProtocolError::InvalidEnumValue { context, .. }
+            | ProtocolError::WrongState { context }
+            | ProtocolError::TrailingBytes { context, .. }
+            | ProtocolError::ReadPacket { context, .. } => context,
+        };
+        context.packet_id = context.packet_id.or(packet_id);
+        context.state = context.state.or(Some(state));
+
+        self
+    }
+
+    /// Whether only the packet is broken. Its frame was read whole, so the connection can go on without it,
+    /// while a broken frame leaves the rest of the stream unreadable.
+    pub fn is_packet_error(&self) -> bool {
+        self.context().packet_id.is_some()
+    }
 }
 
 impl From<anyhow::Error> for ProtocolError {
     fn from(source: anyhow::Error) -> Self {
-        ProtocolError::ReadPacket { source }
+        // Packet reads return anyhow errors, the violations they found are passed on as they are
+        let source = match source.downcast::<ProtocolError>() {
+            Ok(error) => return error,
+            Err(source) => source,
+        };
+        let source = match source.downcast::<std::io::Error>() {
+            Ok(error) => return error.into(),
+            Err(source) => source,
+        };
+
+        if source.is::<std::string::FromUtf8Error>() {
+            return ProtocolError::InvalidUtf8 { context: PacketContext::default() };
+        }
+
+        ProtocolError::ReadPacket { source, context: PacketContext::default() }
+    }
+}
+
+impl From<std::io::Error> for ProtocolError {
+    fn from(source: std::io::Error) -> Self {
+        if source.kind() == std::io::ErrorKind::UnexpectedEof {
+            return ProtocolError::UnexpectedEof { context: PacketContext::default() };
+        }
+
+        ProtocolError::ReadPacket { source: source.into(), context: PacketContext::default() }
     }
 }
@@ -1,24 +1,65 @@
-use error::ProtocolError;
-use packets::{Packet, ReadExactPacket, WriteExactPacket, C2SPacket, S2CPacket, c2s, s2c};
+// Lets `#[derive(Packet)]` refer to `::protocol` inside this crate too
+extern crate self as protocol;
+
+use error::{ProtocolError, PacketContext};
+use packets::{Packet, RawPacket, ReadExactPacket, WriteExactPacket, C2SPacket, S2CPacket, c2s, s2c, registry::{self, PacketKind}};
 use utils::{DataReadExt, DataWriteExt};
+use version::ProtocolVersion;
+use codec::FrameEncoder;
+use bytes::{Bytes, BytesMut};
 
 pub mod utils;
 pub mod packets;
 pub mod error;
 pub mod uuid;
+pub mod compression;
+pub mod crypto;
+pub mod chat;
+pub mod nbt;
+pub mod version;
+pub mod codec;
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum GameStateEnum {
     #[default] Handshake,
     Status,
     Login,
+    /// Between login and play since 1.20.2, entered again when the server sends Start Configuration
+    Configuration,
     Play,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct State {
     pub handshake: Option<c2s::Handshake>,
     pub state: GameStateEnum,
+    /// Negotiated with Set Compression, `None` means compression is disabled.
+    pub compression_threshold: Option<usize>,
+    /// Frames announcing a bigger length are rejected before anything is read or allocated for them.
+    pub max_frame_size: usize,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            handshake: None,
+            state: GameStateEnum::default(),
+            compression_threshold: None,
+            max_frame_size: codec::DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl State {
+    /// Protocol version the client connected with, `None` before the handshake.
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.handshake.as_ref().map(|handshake| ProtocolVersion(handshake.protocol_version))
+    }
+
+    /// Whether the client goes through the configuration state after logging in (1.20.2+).
+    pub fn has_configuration_state(&self) -> bool {
+        self.protocol_version().is_some_and(|protocol_version| protocol_version >= ProtocolVersion::V1_20_2)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -38,97 +79,314 @@ impl From<DirectionEnum> for &str {
 #[async_trait::async_trait]
 pub trait PacketReadExt: DataReadExt + Unpin {
     async fn read_packet(&mut self, state: &State, direction: DirectionEnum) -> Result<Packet, ProtocolError> {
-        match direction {
-            DirectionEnum::C2S => self.read_packet_c2s(state).await,
-            DirectionEnum::S2C => self.read_packet_s2c(state).await,
-        }
+        let frame = self.read_frame(state.max_frame_size).await
+            .map_err(|e| e.at(None, state.state))?;
+        decode_packet(frame, state, direction).await
     }
 
     async fn read_packet_c2s(&mut self, state: &State) -> Result<Packet, ProtocolError> {
-        let (length, mut d1) = self.read_varint_preserve_data().await?;
-        let (packet_id, mut d2) = self.read_varint_preserve_data().await?;
-
-        let packet = match (packet_id, state.state) {
-            (0x00, GameStateEnum::Handshake) => {
-                let handshake = c2s::Handshake::read_packet(self, state).await?;
-                Packet::C2S(C2SPacket::Handshake(handshake))
-            },
-            (0x00, GameStateEnum::Login) => {
-                let login_start = c2s::LoginStart::read_packet(self, state).await?;
-                Packet::C2S(C2SPacket::LoginStart(login_start))
-            },
-            _ => {
-                let mut data = vec![];
-                data.append(&mut d1);
-                let t_sub_len = data.len();
-                let mut rest = vec![0; length as usize - d2.len()];
-                self.read_exact(&mut rest).await.ok();
-
-                data.append(&mut d2);
-                data.append(&mut rest.to_vec());
-
-                assert_eq!(data.len() - t_sub_len, length as usize);
-                return Err(ProtocolError::UnknownPacketId { packet_id, data: data });
-            },
-        };
-
-        Ok(packet)
+        self.read_packet(state, DirectionEnum::C2S).await
     }
 
     async fn read_packet_s2c(&mut self, state: &State) -> Result<Packet, ProtocolError> {
-        let (length, mut d1) = self.read_varint_preserve_data().await?;
-        let (packet_id, mut d2) = self.read_varint_preserve_data().await?;
-        let packet = match (packet_id, state.state) {
-            (0x02, GameStateEnum::Login) => {
-                let login_success = s2c::LoginSuccess::read_packet(self, state).await?;
-                Packet::S2C(S2CPacket::LoginSuccess(login_success))
-            },
-            _ => {
-                let mut data = vec![];
-                data.append(&mut d1);
-                let t_sub_len = data.len();
-                let mut rest = vec![0; length as usize - d2.len()];
-                self.read_exact(&mut rest).await.ok();
-
-                data.append(&mut d2);
-                data.append(&mut rest.to_vec());
-
-                assert_eq!(data.len() - t_sub_len, length as usize);
-                return Err(ProtocolError::UnknownPacketId { packet_id, data: data });
-            },
-        };
+        self.read_packet(state, DirectionEnum::S2C).await
+    }
+
+    /// Read a single frame exactly as it was sent, including the length prefix.
+    async fn read_frame(&mut self, max_frame_size: usize) -> Result<Vec<u8>, ProtocolError> {
+        let (length, mut frame) = self.read_varint_preserve_data().await?;
+        let length = usize::try_from(length).map_err(|_| ProtocolError::NegativeLength { length, context: PacketContext::default() })?;
+        if length > max_frame_size {
+            return Err(ProtocolError::FrameTooLarge { length, max: max_frame_size, context: PacketContext::default() });
+        }
+
+        frame.extend(self.read_bytes(length).await?);
 
-        Ok(packet)
+        Ok(frame)
     }
 }
 
 impl<T: DataReadExt + Unpin> PacketReadExt for T {}
 
+/// A frame read off the wire, decoded if the proxy knows the packet.
+#[derive(Debug, Clone)]
+pub enum Decoded {
+    Packet(Packet),
+    Raw(RawPacket),
+}
+
+/// Decode a frame read with [`PacketReadExt::read_frame`] or [`codec::FrameDecoder`].
+/// Packets the proxy doesn't know about are only peeked at for their id and returned
+/// as [`RawPacket`]s sharing the frame, so they can be passed through as is.
+///
+/// Errors tell the packet id (once it's known) and the state, and the whole packet has to be read,
+/// anything left over is a [`ProtocolError::TrailingBytes`].
+pub async fn decode_frame(frame: Bytes, state: &State, direction: DirectionEnum) -> Result<Decoded, ProtocolError> {
+    let (_, prefix_len) = utils::read_varint_slice(&frame)
+        .map_err(|e| ProtocolError::from(e).at(None, state.state))?;
+    let packet_id = compression::peek_packet_id(&frame[prefix_len..], state.compression_threshold)
+        .map_err(|e| ProtocolError::from(e).at(None, state.state))?;
+    let context = PacketContext { packet_id: Some(packet_id), state: Some(state.state) };
+
+    let version = state.protocol_version().unwrap_or_default();
+    let kind = match registry::packet_kind(version, state.state, direction, packet_id) {
+        Some(kind) => kind,
+        // Most of the play traffic, never decompressed
+        None => return Ok(Decoded::Raw(RawPacket { id: packet_id, frame })),
+    };
+
+    let body = compression::decompress_frame(&frame[prefix_len..], state.compression_threshold)
+        .map_err(|e| ProtocolError::from(e).at(Some(packet_id), state.state))?;
+    let mut reader = &body[..];
+
+    let packet = match read_known_packet(&mut reader, state, direction, kind).await {
+        Ok(Some(packet)) => packet,
+        // System Chat included, the proxy only ever writes it
+        Ok(None) => return Ok(Decoded::Raw(RawPacket { id: packet_id, frame })),
+        Err(e) => return Err(ProtocolError::from(e).at(Some(packet_id), state.state)),
+    };
+
+    if !reader.is_empty() {
+        return Err(ProtocolError::TrailingBytes { length: reader.len(), context });
+    }
+
+    Ok(Decoded::Packet(packet))
+}
+
+/// Read `packet id | data` of a packet the registry knows, `None` for the ones the proxy never reads.
+async fn read_known_packet(
+    reader: &mut &[u8],
+    state: &State,
+    direction: DirectionEnum,
+    kind: PacketKind,
+) -> anyhow::Result<Option<Packet>> {
+    reader.read_varint().await?;
+
+    let packet = match (direction, kind) {
+        (DirectionEnum::C2S, PacketKind::Handshake) => {
+            let handshake = c2s::Handshake::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::Handshake(handshake))
+        },
+        (DirectionEnum::C2S, PacketKind::LoginStart) => {
+            let login_start = c2s::LoginStart::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::LoginStart(login_start))
+        },
+        (DirectionEnum::C2S, PacketKind::EncryptionResponse) => {
+            let encryption_response = c2s::EncryptionResponse::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::EncryptionResponse(encryption_response))
+        },
+        (DirectionEnum::C2S, PacketKind::LoginPluginResponse) => {
+            let login_plugin_response = c2s::LoginPluginResponse::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::LoginPluginResponse(login_plugin_response))
+        },
+        (DirectionEnum::C2S, PacketKind::LoginAcknowledged) => {
+            let login_acknowledged = c2s::LoginAcknowledged::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::LoginAcknowledged(login_acknowledged))
+        },
+        (DirectionEnum::C2S, PacketKind::StatusRequest) => {
+            let status_request = c2s::StatusRequest::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::StatusRequest(status_request))
+        },
+        (DirectionEnum::C2S, PacketKind::PingRequest) => {
+            let ping_request = c2s::PingRequest::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::PingRequest(ping_request))
+        },
+        (DirectionEnum::S2C, PacketKind::StatusResponse) => {
+            let status_response = s2c::StatusResponse::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::StatusResponse(status_response))
+        },
+        (DirectionEnum::S2C, PacketKind::PongResponse) => {
+            let pong_response = s2c::PongResponse::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::PongResponse(pong_response))
+        },
+        (DirectionEnum::S2C, PacketKind::LoginDisconnect) => {
+            let login_disconnect = s2c::LoginDisconnect::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::LoginDisconnect(login_disconnect))
+        },
+        (DirectionEnum::S2C, PacketKind::EncryptionRequest) => {
+            let encryption_request = s2c::EncryptionRequest::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::EncryptionRequest(encryption_request))
+        },
+        (DirectionEnum::S2C, PacketKind::LoginSuccess) => {
+            let login_success = s2c::LoginSuccess::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::LoginSuccess(login_success))
+        },
+        (DirectionEnum::S2C, PacketKind::SetCompression) => {
+            let set_compression = s2c::SetCompression::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::SetCompression(set_compression))
+        },
+        (DirectionEnum::S2C, PacketKind::LoginPluginRequest) => {
+            let login_plugin_request = s2c::LoginPluginRequest::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::LoginPluginRequest(login_plugin_request))
+        },
+        (DirectionEnum::S2C, PacketKind::FinishConfiguration) => {
+            let finish_configuration = s2c::FinishConfiguration::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::FinishConfiguration(finish_configuration))
+        },
+        (DirectionEnum::C2S, PacketKind::AcknowledgeFinishConfiguration) => {
+            let acknowledge_finish_configuration = c2s::AcknowledgeFinishConfiguration::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::AcknowledgeFinishConfiguration(acknowledge_finish_configuration))
+        },
+        (DirectionEnum::S2C, PacketKind::StartConfiguration) => {
+            let start_configuration = s2c::StartConfiguration::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::StartConfiguration(start_configuration))
+        },
+        (DirectionEnum::C2S, PacketKind::AcknowledgeConfiguration) => {
+            let acknowledge_configuration = c2s::AcknowledgeConfiguration::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::AcknowledgeConfiguration(acknowledge_configuration))
+        },
+        (DirectionEnum::C2S, PacketKind::KeepAlive) => {
+            let keep_alive = c2s::KeepAlive::read_packet(reader, state).await?;
+            Packet::C2S(C2SPacket::KeepAlive(keep_alive))
+        },
+        (DirectionEnum::S2C, PacketKind::JoinGame) => {
+            let join_game = s2c::JoinGame::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::JoinGame(join_game))
+        },
+        (DirectionEnum::S2C, PacketKind::Respawn) => {
+            let respawn = s2c::Respawn::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::Respawn(respawn))
+        },
+        (DirectionEnum::S2C, PacketKind::KeepAlive) => {
+            let keep_alive = s2c::KeepAlive::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::KeepAlive(keep_alive))
+        },
+        (DirectionEnum::S2C, PacketKind::Disconnect) => {
+            let disconnect = s2c::Disconnect::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::Disconnect(disconnect))
+        },
+        (DirectionEnum::S2C, PacketKind::ConfigurationDisconnect) => {
+            let disconnect = s2c::Disconnect::read_packet(reader, state).await?;
+            Packet::S2C(S2CPacket::ConfigurationDisconnect(disconnect))
+        },
+        _ => return Ok(None),
+    };
+
+    Ok(Some(packet))
+}
+
+/// Like [`decode_frame`], packets the proxy doesn't know about are returned back
+/// as [`ProtocolError::UnknownPacketId`] with the untouched frame.
+pub async fn decode_packet(frame: Vec<u8>, state: &State, direction: DirectionEnum) -> Result<Packet, ProtocolError> {
+    let raw = match decode_frame(frame.into(), state, direction).await? {
+        Decoded::Packet(packet) => return Ok(packet),
+        Decoded::Raw(raw) => raw,
+    };
+    let context = PacketContext { packet_id: Some(raw.id), state: Some(state.state) };
+
+    match state.state {
+        // The proxy knows every packet of these states, so the id must be one of another state
+        GameStateEnum::Handshake | GameStateEnum::Status => Err(ProtocolError::WrongState { context }),
+        _ => Err(ProtocolError::UnknownPacketId { context, data: raw.frame.into() }),
+    }
+}
+
 #[async_trait::async_trait]
 pub trait PacketWriteExt: DataWriteExt + Unpin {
     async fn write_packet(&mut self, packet: &Packet, state: &State) -> anyhow::Result<()> {
+        let kind = packet.kind();
+        let version = state.protocol_version().unwrap_or_default();
+        let packet_id = registry::packet_id(version, packet.direction(), kind)
+            .ok_or_else(|| anyhow::anyhow!("{:?} packet id is unknown for protocol version {}", kind, version))?;
+
+        let mut data = vec![];
+        data.write_varint(packet_id).await?;
+
         match packet {
             Packet::C2S(c2s_packet) => {
                 match c2s_packet {
                     C2SPacket::Handshake(handshake) => {
-                        handshake.write_packet(self, state).await?;
+                        handshake.write_packet(&mut data, state).await?;
                     },
                     C2SPacket::LoginStart(login_start) => {
-                        login_start.write_packet(self, state).await?;
-                    }
+                        login_start.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::EncryptionResponse(encryption_response) => {
+                        encryption_response.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::StatusRequest(status_request) => {
+                        status_request.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::PingRequest(ping_request) => {
+                        ping_request.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::KeepAlive(keep_alive) => {
+                        keep_alive.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::LoginPluginResponse(login_plugin_response) => {
+                        login_plugin_response.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::LoginAcknowledged(login_acknowledged) => {
+                        login_acknowledged.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::AcknowledgeFinishConfiguration(acknowledge_finish_configuration) => {
+                        acknowledge_finish_configuration.write_packet(&mut data, state).await?;
+                    },
+                    C2SPacket::AcknowledgeConfiguration(acknowledge_configuration) => {
+                        acknowledge_configuration.write_packet(&mut data, state).await?;
+                    },
                 }
             },
             Packet::S2C(s2c_packet) => {
                 match s2c_packet {
                     S2CPacket::LoginSuccess(login_success) => {
-                        login_success.write_packet(self, state).await?;
-                    }
+                        login_success.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::SetCompression(set_compression) => {
+                        set_compression.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::EncryptionRequest(encryption_request) => {
+                        encryption_request.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::StatusResponse(status_response) => {
+                        status_response.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::PongResponse(pong_response) => {
+                        pong_response.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::LoginDisconnect(login_disconnect) => {
+                        login_disconnect.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::JoinGame(join_game) => {
+                        join_game.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::Respawn(respawn) => {
+                        respawn.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::KeepAlive(keep_alive) => {
+                        keep_alive.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::Disconnect(disconnect) | S2CPacket::ConfigurationDisconnect(disconnect) => {
+                        disconnect.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::SystemChat(system_chat) => {
+                        system_chat.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::LoginPluginRequest(login_plugin_request) => {
+                        login_plugin_request.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::FinishConfiguration(finish_configuration) => {
+                        finish_configuration.write_packet(&mut data, state).await?;
+                    },
+                    S2CPacket::StartConfiguration(start_configuration) => {
+                        start_configuration.write_packet(&mut data, state).await?;
+                    },
                 }
             },
         }
 
+        self.write_frame(&data, state).await
+    }
+
+    /// Write `packet id | data` as a single frame, compressing it if needed.
+    async fn write_frame(&mut self, data: &[u8], state: &State) -> anyhow::Result<()> {
+        let mut frame = BytesMut::new();
+        FrameEncoder::new(state.compression_threshold).encode(data, &mut frame)?;
+
+        self.write_all(&frame).await?;
+
         Ok(())
     }
 }
 
-impl<T: DataWriteExt + Unpin> PacketWriteExt for T {}
\ No newline at end of file
+impl<T: DataWriteExt + Unpin> PacketWriteExt for T {}
@@ -0,0 +1,354 @@
+use serde_json::{Map, Number, Value};
+use tokio::io::AsyncReadExt;
+
+use crate::utils::DataReadExt;
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_DOUBLE: u8 = 6;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+
+/// Deeper nesting is rejected, vanilla allows 512 levels as well.
+const MAX_DEPTH: usize = 512;
+
+enum Container {
+    Compound,
+    List { tag: u8, remaining: i32 },
+}
+
+/// Read a named NBT tag without interpreting it.
+/// The proxy never looks inside NBT (e.g. the registry codec), so it's kept
+/// as raw bytes and written back as is.
+pub async fn read_raw_nbt<R: DataReadExt + Send>(reader: &mut R) -> anyhow::Result<Vec<u8>> {
+    read_raw(reader, true).await
+}
+
+/// Read an NBT tag as it's sent since 1.20.2, the root has no name.
+pub async fn read_raw_network_nbt<R: DataReadExt + Send>(reader: &mut R) -> anyhow::Result<Vec<u8>> {
+    read_raw(reader, false).await
+}
+
+async fn read_raw<R: DataReadExt + Send>(reader: &mut R, named: bool) -> anyhow::Result<Vec<u8>> {
+    let mut raw = vec![];
+
+    let tag = reader.read_u8().await?;
+    raw.push(tag);
+    if tag == TAG_END {
+        return Ok(raw);
+    }
+
+    if named {
+        read_name(reader, &mut raw).await?;
+    }
+
+    let mut stack = vec![];
+    read_payload(reader, tag, &mut raw, &mut stack).await?;
+
+    while let Some(container) = stack.last_mut() {
+        let tag = match container {
+            Container::Compound => {
+                let tag = reader.read_u8().await?;
+                raw.push(tag);
+                if tag == TAG_END {
+                    stack.pop();
+                    continue;
+                }
+
+                read_name(reader, &mut raw).await?;
+                tag
+            },
+            Container::List { tag, remaining } => {
+                if *remaining <= 0 {
+                    stack.pop();
+                    continue;
+                }
+
+                *remaining -= 1;
+                *tag
+            },
+        };
+
+        read_payload(reader, tag, &mut raw, &mut stack).await?;
+        if stack.len() > MAX_DEPTH {
+            return Err(anyhow::anyhow!("NBT is nested too deep"));
+        }
+    }
+
+    Ok(raw)
+}
+
+async fn read_name<R: DataReadExt + Send>(reader: &mut R, raw: &mut Vec<u8>) -> anyhow::Result<()> {
+    let length = reader.read_u16().await?;
+    raw.extend_from_slice(&length.to_be_bytes());
+
+    copy_bytes(reader, length as usize, raw).await
+}
+
+/// Copy the payload of a tag, compounds and lists are pushed on the stack instead.
+async fn read_payload<R: DataReadExt + Send>(
+    reader: &mut R,
+    tag: u8,
+    raw: &mut Vec<u8>,
+    stack: &mut Vec<Container>
+) -> anyhow::Result<()> {
+    match tag {
+        // Byte, Short, Int, Long, Float, Double
+        1 => copy_bytes(reader, 1, raw).await,
+        2 => copy_bytes(reader, 2, raw).await,
+        3 | 5 => copy_bytes(reader, 4, raw).await,
+        4 | 6 => copy_bytes(reader, 8, raw).await,
+        // Byte Array, Int Array, Long Array
+        7 | 11 | 12 => {
+            let length = reader.read_i32().await?;
+            raw.extend_from_slice(&length.to_be_bytes());
+
+            let length = usize::try_from(length)
+                .map_err(|_| anyhow::anyhow!("Negative NBT array length: {}", length))?;
+            let size = match tag {
+                7 => 1,
+                11 => 4,
+                _ => 8,
+            };
+
+            copy_bytes(reader, length * size, raw).await
+        },
+        // String
+        8 => read_name(reader, raw).await,
+        TAG_LIST => {
+            let tag = reader.read_u8().await?;
+            let remaining = reader.read_i32().await?;
+            raw.push(tag);
+            raw.extend_from_slice(&remaining.to_be_bytes());
+
+            stack.push(Container::List { tag, remaining });
+            Ok(())
+        },
+        TAG_COMPOUND => {
+            stack.push(Container::Compound);
+            Ok(())
+        },
+        tag => Err(anyhow::anyhow!("Unknown NBT tag type: {}", tag)),
+    }
+}
+
+async fn copy_bytes<R: DataReadExt + Send>(reader: &mut R, length: usize, raw: &mut Vec<u8>) -> anyhow::Result<()> {
+    // Don't trust the length for allocating, the data has to actually be there
+    let read = reader.take(length as u64).read_to_end(raw).await?;
+    if read != length {
+        return Err(anyhow::anyhow!("Unexpected end of NBT data"));
+    }
+
+    Ok(())
+}
+
+/// Read a network NBT tag into the JSON it stands for, text components are sent as NBT since 1.20.3.
+/// Bytes become booleans, text components only use them for style flags.
+pub async fn read_nbt_json<R: DataReadExt + Send>(reader: &mut R) -> anyhow::Result<Value> {
+    let raw = read_raw_network_nbt(reader).await?;
+    let mut data = &raw[1..];
+
+    to_json(raw[0], &mut data)
+}
+
+/// Write JSON (like a text component) as a network NBT tag.
+pub fn write_nbt_json(value: &Value, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    out.push(tag_of(value)?);
+    write_payload(value, out)
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> anyhow::Result<&'a [u8]> {
+    if data.len() < length {
+        return Err(anyhow::anyhow!("Unexpected end of NBT data"));
+    }
+
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+
+    Ok(taken)
+}
+
+fn take_array<const N: usize>(data: &mut &[u8]) -> anyhow::Result<[u8; N]> {
+    Ok(take(data, N)?.try_into()?)
+}
+
+fn take_length(data: &mut &[u8]) -> anyhow::Result<usize> {
+    let length = i32::from_be_bytes(take_array(data)?);
+
+    usize::try_from(length).map_err(|_| anyhow::anyhow!("Negative NBT array length: {}", length))
+}
+
+/// The raw data was already checked by [`read_raw`], so this can't nest too deep.
+fn to_json(tag: u8, data: &mut &[u8]) -> anyhow::Result<Value> {
+    let value = match tag {
+        TAG_BYTE => Value::Bool(take_array::<1>(data)?[0] != 0),
+        2 => i16::from_be_bytes(take_array(data)?).into(),
+        TAG_INT => i32::from_be_bytes(take_array(data)?).into(),
+        TAG_LONG => i64::from_be_bytes(take_array(data)?).into(),
+        5 => f32::from_be_bytes(take_array(data)?).into(),
+        TAG_DOUBLE => f64::from_be_bytes(take_array(data)?).into(),
+        7 => {
+            let length = take_length(data)?;
+            take(data, length)?.iter().map(|byte| Value::from(*byte as i8)).collect()
+        },
+        11 => {
+            let length = take_length(data)?;
+            (0..length).map(|_| Ok(Value::from(i32::from_be_bytes(take_array(data)?)))).collect::<anyhow::Result<_>>()?
+        },
+        12 => {
+            let length = take_length(data)?;
+            (0..length).map(|_| Ok(Value::from(i64::from_be_bytes(take_array(data)?)))).collect::<anyhow::Result<_>>()?
+        },
+        TAG_STRING => Value::String(take_string(data)?),
+        TAG_LIST => {
+            let tag = take_array::<1>(data)?[0];
+            let length = take_length(data)?;
+
+            let mut list = vec![];
+            for _ in 0..length {
+                // Lists of mixed types are written as compounds with a single unnamed entry
+                let value = match to_json(tag, data)? {
+                    Value::Object(mut object) if object.len() == 1 && object.contains_key("") => object.remove("").unwrap(),
+                    value => value,
+                };
+                list.push(value);
+            }
+
+            Value::Array(list)
+        },
+        TAG_COMPOUND => {
+            let mut object = Map::new();
+            loop {
+                let tag = take_array::<1>(data)?[0];
+                if tag == TAG_END {
+                    break;
+                }
+
+                let name = take_string(data)?;
+                object.insert(name, to_json(tag, data)?);
+            }
+
+            Value::Object(object)
+        },
+        tag => return Err(anyhow::anyhow!("Unknown NBT tag type: {}", tag)),
+    };
+
+    Ok(value)
+}
+
+fn tag_of(value: &Value) -> anyhow::Result<u8> {
+    match value {
+        Value::Bool(_) => Ok(TAG_BYTE),
+        Value::Number(number) => Ok(number_tag(number)),
+        Value::String(_) => Ok(TAG_STRING),
+        Value::Array(_) => Ok(TAG_LIST),
+        Value::Object(_) => Ok(TAG_COMPOUND),
+        Value::Null => Err(anyhow::anyhow!("NBT has no null")),
+    }
+}
+
+fn number_tag(number: &Number) -> u8 {
+    match number.as_i64() {
+        Some(value) if i32::try_from(value).is_ok() => TAG_INT,
+        Some(_) => TAG_LONG,
+        None => TAG_DOUBLE,
+    }
+}
+
+fn write_payload(value: &Value, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    match value {
+        Value::Bool(value) => out.push(*value as u8),
+        Value::Number(number) => match number_tag(number) {
+            TAG_INT => out.extend_from_slice(&(number.as_i64().unwrap() as i32).to_be_bytes()),
+            TAG_LONG => out.extend_from_slice(&number.as_i64().unwrap().to_be_bytes()),
+            _ => out.extend_from_slice(&number.as_f64().unwrap_or_default().to_be_bytes()),
+        },
+        Value::String(value) => write_string(value, out)?,
+        Value::Array(list) => {
+            let tags = list.iter().map(tag_of).collect::<anyhow::Result<Vec<_>>>()?;
+            let tag = match tags.first() {
+                Some(first) if tags.iter().all(|tag| tag == first) => *first,
+                Some(_) => TAG_COMPOUND,
+                None => TAG_END,
+            };
+
+            out.push(tag);
+            out.extend_from_slice(&i32::try_from(list.len())?.to_be_bytes());
+            for (value, value_tag) in list.iter().zip(tags) {
+                if tag == TAG_COMPOUND && value_tag != TAG_COMPOUND {
+                    // Wrapped the way vanilla does it, see `to_json`
+                    out.push(value_tag);
+                    write_string("", out)?;
+                    write_payload(value, out)?;
+                    out.push(TAG_END);
+                } else {
+                    write_payload(value, out)?;
+                }
+            }
+        },
+        Value::Object(object) => {
+            for (name, value) in object {
+                if value.is_null() {
+                    continue;
+                }
+
+                out.push(tag_of(value)?);
+                write_string(name, out)?;
+                write_payload(value, out)?;
+            }
+            out.push(TAG_END);
+        },
+        Value::Null => return Err(anyhow::anyhow!("NBT has no null")),
+    }
+
+    Ok(())
+}
+
+/// NBT strings are Java's modified UTF-8: UTF-16 code units, and NUL takes two bytes.
+fn write_string(value: &str, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let mut encoded = vec![];
+    for unit in value.encode_utf16() {
+        match unit {
+            0x01..=0x7f => encoded.push(unit as u8),
+            0x00 | 0x80..=0x7ff => {
+                encoded.push(0xc0 | (unit >> 6) as u8);
+                encoded.push(0x80 | (unit & 0x3f) as u8);
+            },
+            _ => {
+                encoded.push(0xe0 | (unit >> 12) as u8);
+                encoded.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+                encoded.push(0x80 | (unit & 0x3f) as u8);
+            },
+        }
+    }
+
+    let length = u16::try_from(encoded.len()).map_err(|_| anyhow::anyhow!("NBT string is too long"))?;
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(&encoded);
+
+    Ok(())
+}
+
+fn take_string(data: &mut &[u8]) -> anyhow::Result<String> {
+    let length = u16::from_be_bytes(take_array(data)?) as usize;
+    let mut bytes = take(data, length)?.iter();
+
+    let mut units = vec![];
+    while let Some(&byte) = bytes.next() {
+        let mut continuation = || bytes.next().map(|byte| (byte & 0x3f) as u16)
+            .ok_or_else(|| anyhow::anyhow!("Invalid modified UTF-8 in NBT string"));
+
+        let unit = match byte {
+            0x00..=0x7f => byte as u16,
+            0xc0..=0xdf => ((byte & 0x1f) as u16) << 6 | continuation()?,
+            0xe0..=0xef => ((byte & 0x0f) as u16) << 12 | continuation()? << 6 | continuation()?,
+            _ => return Err(anyhow::anyhow!("Invalid modified UTF-8 in NBT string")),
+        };
+        units.push(unit);
+    }
+
+    Ok(String::from_utf16(&units)?)
+}
@@ -1,12 +1,18 @@
-use tokio::io::AsyncWriteExt;
-use crate::{utils::{DataReadExt, DataWriteExt}, State, uuid::UUID3};
+use crate::{utils::{DataReadExt, DataWriteExt}, State, uuid::UUID3, version::ProtocolVersion, error::{ProtocolError, PacketContext}};
 
-use super::{ReadExactPacket, WriteExactPacket};
+use super::{ReadExactPacket, WriteExactPacket, Packet, field::PacketField, protocol_version};
+
+/// Longest server address vanilla servers accept in the handshake
+pub const MAX_SERVER_ADDRESS_LENGTH: usize = 255;
+/// Longest username vanilla servers accept
+pub const MAX_USERNAME_LENGTH: usize = 16;
 
 /// Handshake packet
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Packet)]
 pub struct Handshake {
+    #[varint]
     pub protocol_version: i32,
+    #[max_length(MAX_SERVER_ADDRESS_LENGTH)]
     pub server_address: String,
     pub server_port: u16,
     pub next_state: NextState,
@@ -28,119 +34,216 @@ impl From<NextState> for i32 {
 }
 
 #[async_trait::async_trait]
-impl ReadExactPacket for Handshake {
+impl PacketField for NextState {
+    async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self> {
+        match reader.read_varint().await? {
+            1 => Ok(NextState::Status),
+            2 => Ok(NextState::Login),
+            value => Err(ProtocolError::InvalidEnumValue { name: "next state", value, context: PacketContext::default() }.into()),
+        }
+    }
+
+    async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()> {
+        writer.write_varint(self.clone().into()).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginStart {
+    pub username: String,
+    /// Profile key of 1.19 - 1.19.2 clients, `None` if the player has none
+    pub signature_data: Option<SignatureData>,
+    /// Optional for 1.19.1 - 1.20.1 clients, always sent since 1.20.2
+    pub player_uuid: Option<UUID3>,
+}
+
+/// Profile public key a 1.19 - 1.19.2 client sends with its login start,
+/// signed by Mojang. The client signs its encryption response with it.
+#[derive(Debug, Clone)]
+pub struct SignatureData {
+    /// When the key expires, in milliseconds since the epoch
+    pub timestamp: i64,
+    /// DER encoded RSA public key
+    pub public_key: Vec<u8>,
+    /// Mojang's signature of the key
+    pub signature: Vec<u8>,
+}
+
+#[async_trait::async_trait]
+impl ReadExactPacket for LoginStart {
     async fn read_packet(
         mut reader: impl DataReadExt + std::marker::Send, 
-        _state: &State
+        state: &State
     ) -> anyhow::Result<Self> where Self: Sized {
-        let protocol_version = reader.read_varint().await.unwrap();
-        let server_address = reader.read_string().await.unwrap();
-        let server_port = reader.read_u16().await.unwrap();
-        let next_state = match reader.read_varint().await.unwrap() {
-            1 => NextState::Status,
-            2 => NextState::Login,
-            _ => {
-                return Err(anyhow::anyhow!("Invalid next state"));
-            },
+        let protocol_version = protocol_version(state)?;
+
+        let username = reader.read_string_bounded(MAX_USERNAME_LENGTH).await?;
+
+        let signature_data = if (ProtocolVersion::V1_19..=ProtocolVersion::V1_19_1).contains(&protocol_version)
+            && reader.read_bool().await?
+        {
+            Some(SignatureData {
+                timestamp: reader.read_i64().await?,
+                public_key: reader.read_byte_array().await?,
+                signature: reader.read_byte_array().await?,
+            })
+        } else {
+            None
+        };
+
+        // Required since 1.20.2, behind a presence flag before that
+        let player_uuid = if protocol_version >= ProtocolVersion::V1_20_2
+            || (protocol_version >= ProtocolVersion::V1_19_1 && reader.read_bool().await?)
+        {
+            Some(reader.read_uuid().await?)
+        } else {
+            None
         };
 
         Ok(Self {
-            protocol_version,
-            server_address,
-            server_port,
-            next_state,
+            username, signature_data, player_uuid,
         })
     }
 }
 
 #[async_trait::async_trait]
-impl WriteExactPacket for Handshake {
+impl WriteExactPacket for LoginStart {
     async fn write_packet(
         &self, 
         mut writer: impl DataWriteExt + std::marker::Send, 
-        _state: &State
+        state: &State
     ) -> anyhow::Result<()> {
-        let mut data = vec![];
+        let protocol_version = protocol_version(state)?;
 
-        data.write_varint(self.protocol_version).await?;
-        data.write_string(&self.server_address).await?;
-        data.write_u16(self.server_port).await?;
-        data.write_varint(self.next_state.clone().into()).await?;
+        writer.write_string(&self.username).await?;
 
-        writer.write_varint(data.len() as i32 + 1).await?;
-        writer.write_u8(0x00).await?;
-        writer.write_all(&data).await?;
+        if (ProtocolVersion::V1_19..=ProtocolVersion::V1_19_1).contains(&protocol_version) {
+            writer.write_bool(self.signature_data.is_some()).await?;
+            if let Some(signature_data) = &self.signature_data {
+                writer.write_i64(signature_data.timestamp).await?;
+                writer.write_byte_array(&signature_data.public_key).await?;
+                writer.write_byte_array(&signature_data.signature).await?;
+            }
+        }
+
+        if protocol_version >= ProtocolVersion::V1_20_2 {
+            let uuid = self.player_uuid
+                .ok_or_else(|| anyhow::anyhow!("Player UUID is None, but protocol version is 1.20.2+"))?;
+            writer.write_uuid(uuid).await?;
+        } else if protocol_version >= ProtocolVersion::V1_19_1 {
+            writer.write_bool(self.player_uuid.is_some()).await?;
+            if let Some(uuid) = self.player_uuid {
+                writer.write_uuid(uuid).await?;
+            }
+        }
 
         Ok(())
     }
 }
 
+/// Encryption Response packet, both fields are encrypted with the server's public key
 #[derive(Debug, Clone)]
-pub struct LoginStart {
-    pub username: String,
-    pub player_uuid: Option<UUID3>,
+pub struct EncryptionResponse {
+    pub shared_secret: Vec<u8>,
+    /// Not sent by 1.19 - 1.19.2 clients which sign the response instead
+    pub verify_token: Option<Vec<u8>>,
+    /// Salt and signature of the verify token, only sent by 1.19 - 1.19.2 clients
+    pub signature: Option<(i64, Vec<u8>)>,
 }
 
 #[async_trait::async_trait]
-impl ReadExactPacket for LoginStart {
+impl ReadExactPacket for EncryptionResponse {
     async fn read_packet(
         mut reader: impl DataReadExt + std::marker::Send, 
         state: &State
     ) -> anyhow::Result<Self> where Self: Sized {
-        if state.handshake.is_none() {
-            return Err(anyhow::anyhow!("Handshake packet not received"));
-        }
-        let handshake = state.handshake.as_ref().unwrap();
-
-        let username = reader.read_string().await?;
-        
-        // Not sure here if it's 735
-        let player_uuid = if handshake.protocol_version >= 735 {
-            if reader.read_bool().await.unwrap() {
-                Some(reader.read_uuid().await?)
-            } else {
-                None
-            }
+        let protocol_version = protocol_version(state)?;
+
+        let shared_secret = reader.read_byte_array().await?;
+
+        let (verify_token, signature) = if (ProtocolVersion::V1_19..=ProtocolVersion::V1_19_1).contains(&protocol_version)
+            && !reader.read_bool().await? 
+        {
+            let salt = reader.read_i64().await?;
+            (None, Some((salt, reader.read_byte_array().await?)))
         } else {
-            None
+            (Some(reader.read_byte_array().await?), None)
         };
 
-
         Ok(Self {
-            username, player_uuid,
+            shared_secret, verify_token, signature,
         })
     }
 }
 
 #[async_trait::async_trait]
-impl WriteExactPacket for LoginStart {
+impl WriteExactPacket for EncryptionResponse {
     async fn write_packet(
         &self, 
         mut writer: impl DataWriteExt + std::marker::Send, 
         state: &State
     ) -> anyhow::Result<()> {
-        if state.handshake.is_none() {
-            return Err(anyhow::anyhow!("Handshake packet not received"));
-        }
-        let handshake = state.handshake.as_ref().unwrap();
-        
-        let mut data = vec![];
+        let protocol_version = protocol_version(state)?;
 
-        data.write_string(&self.username).await?;
+        writer.write_byte_array(&self.shared_secret).await?;
 
-        if handshake.protocol_version >= 735 {
-            if let Some(uuid) = self.player_uuid {
-                data.write_bool(true).await?;
-                data.write_uuid(uuid).await?;
-            } else {
-                data.write_bool(false).await?;
-            }
+        if (ProtocolVersion::V1_19..=ProtocolVersion::V1_19_1).contains(&protocol_version) {
+            writer.write_bool(self.verify_token.is_some()).await?;
         }
 
-        writer.write_varint(data.len() as i32 + 1).await?;
-        writer.write_u8(0x00).await?;
-        writer.write_all(&data).await?;
+        match (&self.verify_token, &self.signature) {
+            (Some(verify_token), _) => {
+                writer.write_byte_array(verify_token).await?;
+            },
+            (None, Some((salt, signature))) => {
+                writer.write_i64(*salt).await?;
+                writer.write_byte_array(signature).await?;
+            },
+            (None, None) => {
+                return Err(anyhow::anyhow!("Neither verify token nor signature is set"));
+            },
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Login Plugin Response packet, answers a login plugin request of the server
+#[derive(Debug, Clone, Packet)]
+pub struct LoginPluginResponse {
+    #[varint]
+    pub message_id: i32,
+    /// `None` if the client didn't understand the request
+    #[optional]
+    #[rest]
+    pub data: Option<Vec<u8>>,
+}
+
+/// Login Acknowledged packet, the client moves on to the configuration state (1.20.2+)
+#[derive(Debug, Clone, Packet)]
+pub struct LoginAcknowledged;
+
+/// Acknowledge Finish Configuration packet, the client moves on to the play state
+#[derive(Debug, Clone, Packet)]
+pub struct AcknowledgeFinishConfiguration;
+
+/// Acknowledge Configuration packet, the client goes back to the configuration state
+#[derive(Debug, Clone, Packet)]
+pub struct AcknowledgeConfiguration;
+
+/// Status Request packet, asks the server for its status (server list ping)
+#[derive(Debug, Clone, Packet)]
+pub struct StatusRequest;
+
+/// Ping Request packet, the payload gets echoed back with the pong
+#[derive(Debug, Clone, Packet)]
+pub struct PingRequest {
+    pub payload: i64,
+}
+
+/// Keep Alive packet of the play state, answers the server's keep alive
+#[derive(Debug, Clone, Packet)]
+pub struct KeepAlive {
+    pub id: i64,
+}
+
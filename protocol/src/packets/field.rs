@@ -0,0 +1,106 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{utils::{DataReadExt, DataWriteExt}, uuid::UUID3, version::ProtocolVersion};
+
+/// Types `#[derive(Packet)]` reads and writes without any field attribute.
+#[async_trait::async_trait]
+pub trait PacketField: Sized + Send + Sync {
+    async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self>;
+    async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()>;
+}
+
+macro_rules! number_field {
+    ($($ty:ty => $read:ident, $write:ident;)*) => {
+        $(
+            #[async_trait::async_trait]
+            impl PacketField for $ty {
+                async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self> {
+                    Ok(reader.$read().await?)
+                }
+
+                async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()> {
+                    Ok(writer.$write(*self).await?)
+                }
+            }
+        )*
+    };
+}
+
+number_field! {
+    u8 => read_u8, write_u8;
+    i8 => read_i8, write_i8;
+    u16 => read_u16, write_u16;
+    i16 => read_i16, write_i16;
+    i32 => read_i32, write_i32;
+    i64 => read_i64, write_i64;
+    f32 => read_f32, write_f32;
+    f64 => read_f64, write_f64;
+}
+
+#[async_trait::async_trait]
+impl PacketField for bool {
+    async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self> {
+        reader.read_bool().await
+    }
+
+    async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()> {
+        writer.write_bool(*self).await
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketField for String {
+    async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self> {
+        reader.read_string().await
+    }
+
+    async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()> {
+        writer.write_string(self).await
+    }
+}
+
+#[async_trait::async_trait]
+impl PacketField for UUID3 {
+    async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self> {
+        reader.read_uuid().await
+    }
+
+    async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()> {
+        writer.write_uuid(*self).await
+    }
+}
+
+/// VarInt length of a `#[length_prefixed]` field
+pub async fn read_length(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<usize> {
+    reader.read_length().await
+}
+
+pub async fn write_length(writer: &mut (impl DataWriteExt + Send), length: usize) -> anyhow::Result<()> {
+    writer.write_varint(i32::try_from(length)?).await
+}
+
+/// `#[json]` field, a string with JSON inside (chat components, server status)
+pub async fn read_json<T: DeserializeOwned>(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<T> {
+    Ok(serde_json::from_str(&reader.read_string().await?)?)
+}
+
+pub async fn write_json<T: Serialize + Sync>(writer: &mut (impl DataWriteExt + Send), value: &T) -> anyhow::Result<()> {
+    writer.write_string(&serde_json::to_string(value)?).await
+}
+
+/// `#[rest]` field, everything left in the packet
+pub async fn read_rest(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Vec<u8>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data).await?;
+
+    Ok(data)
+}
+
+pub async fn write_rest(writer: &mut (impl DataWriteExt + Send), data: &[u8]) -> anyhow::Result<()> {
+    Ok(writer.write_all(data).await?)
+}
+
+/// Error for a `#[since]`/`#[until]` field left `None` although the protocol version needs it
+pub fn missing_field(name: &str, protocol_version: ProtocolVersion) -> anyhow::Error {
+    anyhow::anyhow!("Field {} is required by protocol version {}", name, protocol_version)
+}
@@ -1,17 +1,71 @@
-use crate::{utils::{DataReadExt, DataWriteExt}, State};
+use crate::{utils::{DataReadExt, DataWriteExt}, State, GameStateEnum, DirectionEnum, chat::ChatComponent, version::ProtocolVersion};
+use bytes::Bytes;
+use registry::PacketKind;
 
 pub mod c2s;
 pub mod s2c;
+pub mod registry;
+pub mod field;
+
+pub use protocol_derive::Packet;
 
 #[derive(Debug, Clone)]
 pub enum C2SPacket {
     Handshake(c2s::Handshake),
     LoginStart(c2s::LoginStart),
+    EncryptionResponse(c2s::EncryptionResponse),
+    StatusRequest(c2s::StatusRequest),
+    PingRequest(c2s::PingRequest),
+    KeepAlive(c2s::KeepAlive),
+    LoginPluginResponse(c2s::LoginPluginResponse),
+    LoginAcknowledged(c2s::LoginAcknowledged),
+    AcknowledgeFinishConfiguration(c2s::AcknowledgeFinishConfiguration),
+    AcknowledgeConfiguration(c2s::AcknowledgeConfiguration),
 }
 
 #[derive(Debug, Clone)]
 pub enum S2CPacket {
     LoginSuccess(s2c::LoginSuccess),
+    SetCompression(s2c::SetCompression),
+    EncryptionRequest(s2c::EncryptionRequest),
+    StatusResponse(s2c::StatusResponse),
+    PongResponse(s2c::PongResponse),
+    LoginDisconnect(s2c::LoginDisconnect),
+    JoinGame(s2c::JoinGame),
+    Respawn(s2c::Respawn),
+    KeepAlive(s2c::KeepAlive),
+    Disconnect(s2c::Disconnect),
+    ConfigurationDisconnect(s2c::Disconnect),
+    SystemChat(s2c::SystemChat),
+    LoginPluginRequest(s2c::LoginPluginRequest),
+    FinishConfiguration(s2c::FinishConfiguration),
+    StartConfiguration(s2c::StartConfiguration),
+}
+
+impl S2CPacket {
+    /// Packet kicking the client in the given state,
+    /// `None` if the client can't be shown a reason there.
+    pub fn disconnect(state: &State, reason: ChatComponent) -> Option<Self> {
+        let (kind, packet) = match state.state {
+            GameStateEnum::Login => (PacketKind::LoginDisconnect, S2CPacket::LoginDisconnect(s2c::LoginDisconnect { reason })),
+            GameStateEnum::Configuration => (PacketKind::ConfigurationDisconnect, S2CPacket::ConfigurationDisconnect(s2c::Disconnect { reason })),
+            GameStateEnum::Play => (PacketKind::Disconnect, S2CPacket::Disconnect(s2c::Disconnect { reason })),
+            GameStateEnum::Handshake | GameStateEnum::Status => return None,
+        };
+
+        // Versions the registry doesn't know yet
+        registry::packet_id(state.protocol_version()?, DirectionEnum::S2C, kind)?;
+
+        Some(packet)
+    }
+}
+
+/// Packet passed through without being decoded
+#[derive(Debug, Clone)]
+pub struct RawPacket {
+    pub id: i32,
+    /// Exactly as it was received, length prefix included
+    pub frame: Bytes,
 }
 
 #[derive(Debug, Clone)]
@@ -20,6 +74,55 @@ pub enum Packet {
     S2C(S2CPacket),
 }
 
+impl Packet {
+    pub fn direction(&self) -> DirectionEnum {
+        match self {
+            Packet::C2S(_) => DirectionEnum::C2S,
+            Packet::S2C(_) => DirectionEnum::S2C,
+        }
+    }
+
+    pub fn kind(&self) -> PacketKind {
+        match self {
+            Packet::C2S(c2s_packet) => match c2s_packet {
+                C2SPacket::Handshake(_) => PacketKind::Handshake,
+                C2SPacket::LoginStart(_) => PacketKind::LoginStart,
+                C2SPacket::EncryptionResponse(_) => PacketKind::EncryptionResponse,
+                C2SPacket::StatusRequest(_) => PacketKind::StatusRequest,
+                C2SPacket::PingRequest(_) => PacketKind::PingRequest,
+                C2SPacket::KeepAlive(_) => PacketKind::KeepAlive,
+                C2SPacket::LoginPluginResponse(_) => PacketKind::LoginPluginResponse,
+                C2SPacket::LoginAcknowledged(_) => PacketKind::LoginAcknowledged,
+                C2SPacket::AcknowledgeFinishConfiguration(_) => PacketKind::AcknowledgeFinishConfiguration,
+                C2SPacket::AcknowledgeConfiguration(_) => PacketKind::AcknowledgeConfiguration,
+            },
+            Packet::S2C(s2c_packet) => match s2c_packet {
+                S2CPacket::LoginSuccess(_) => PacketKind::LoginSuccess,
+                S2CPacket::SetCompression(_) => PacketKind::SetCompression,
+                S2CPacket::EncryptionRequest(_) => PacketKind::EncryptionRequest,
+                S2CPacket::StatusResponse(_) => PacketKind::StatusResponse,
+                S2CPacket::PongResponse(_) => PacketKind::PongResponse,
+                S2CPacket::LoginDisconnect(_) => PacketKind::LoginDisconnect,
+                S2CPacket::JoinGame(_) => PacketKind::JoinGame,
+                S2CPacket::Respawn(_) => PacketKind::Respawn,
+                S2CPacket::KeepAlive(_) => PacketKind::KeepAlive,
+                S2CPacket::Disconnect(_) => PacketKind::Disconnect,
+                S2CPacket::ConfigurationDisconnect(_) => PacketKind::ConfigurationDisconnect,
+                S2CPacket::SystemChat(_) => PacketKind::SystemChat,
+                S2CPacket::LoginPluginRequest(_) => PacketKind::LoginPluginRequest,
+                S2CPacket::FinishConfiguration(_) => PacketKind::FinishConfiguration,
+                S2CPacket::StartConfiguration(_) => PacketKind::StartConfiguration,
+            },
+        }
+    }
+}
+
+/// Protocol version for packets whose layout depends on it.
+pub fn protocol_version(state: &State) -> anyhow::Result<ProtocolVersion> {
+    state.protocol_version()
+        .ok_or_else(|| anyhow::anyhow!("Handshake packet not received"))
+}
+
 #[async_trait::async_trait]
 pub trait ReadExactPacket {
     async fn read_packet(
@@ -28,6 +131,8 @@ pub trait ReadExactPacket {
     ) -> anyhow::Result<Self> where Self: Sized;
 }
 
+/// Writes packet data. The packet id (looked up in the [`registry`]), length prefixing
+/// and compression are handled by [`crate::PacketWriteExt`].
 #[async_trait::async_trait]
 pub trait WriteExactPacket {
     async fn write_packet(
@@ -0,0 +1,286 @@
+use std::ops::RangeInclusive;
+
+use crate::{GameStateEnum, DirectionEnum, version::ProtocolVersion};
+
+/// Packets the proxy knows, each of them belongs to a single state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketKind {
+    Handshake,
+    StatusRequest,
+    StatusResponse,
+    PingRequest,
+    PongResponse,
+    LoginStart,
+    LoginDisconnect,
+    EncryptionRequest,
+    EncryptionResponse,
+    LoginSuccess,
+    SetCompression,
+    LoginPluginRequest,
+    LoginPluginResponse,
+    LoginAcknowledged,
+    ConfigurationDisconnect,
+    FinishConfiguration,
+    AcknowledgeFinishConfiguration,
+    StartConfiguration,
+    AcknowledgeConfiguration,
+    JoinGame,
+    Respawn,
+    KeepAlive,
+    Disconnect,
+    SystemChat,
+}
+
+impl PacketKind {
+    pub fn state(self) -> GameStateEnum {
+        match self {
+            PacketKind::Handshake => GameStateEnum::Handshake,
+            PacketKind::StatusRequest | PacketKind::StatusResponse
+                | PacketKind::PingRequest | PacketKind::PongResponse => GameStateEnum::Status,
+            PacketKind::LoginStart | PacketKind::LoginDisconnect | PacketKind::EncryptionRequest
+                | PacketKind::EncryptionResponse | PacketKind::LoginSuccess | PacketKind::SetCompression
+                | PacketKind::LoginPluginRequest | PacketKind::LoginPluginResponse
+                | PacketKind::LoginAcknowledged => GameStateEnum::Login,
+            PacketKind::ConfigurationDisconnect | PacketKind::FinishConfiguration
+                | PacketKind::AcknowledgeFinishConfiguration => GameStateEnum::Configuration,
+            PacketKind::StartConfiguration | PacketKind::AcknowledgeConfiguration | PacketKind::JoinGame
+                | PacketKind::Respawn | PacketKind::KeepAlive | PacketKind::Disconnect
+                | PacketKind::SystemChat => GameStateEnum::Play,
+        }
+    }
+}
+
+/// Id of the packet in the protocol version, `None` if the version doesn't have it or it's unknown.
+pub fn packet_id(version: ProtocolVersion, direction: DirectionEnum, kind: PacketKind) -> Option<i32> {
+    entries(version)
+        .find(|(entry_direction, entry_kind, _)| *entry_direction == direction && *entry_kind == kind)
+        .map(|(_, _, packet_id)| *packet_id)
+}
+
+/// Which packet the id stands for in the protocol version and state, `None` for the ones the proxy doesn't know.
+pub fn packet_kind(version: ProtocolVersion, state: GameStateEnum, direction: DirectionEnum, packet_id: i32) -> Option<PacketKind> {
+    entries(version)
+        .find(|(entry_direction, kind, entry_id)| {
+            *entry_direction == direction && *entry_id == packet_id && kind.state() == state
+        })
+        .map(|(_, kind, _)| *kind)
+}
+
+fn entries(version: ProtocolVersion) -> impl Iterator<Item = &'static Entry> {
+    TABLES.iter()
+        .filter(move |table| table.versions.contains(&version))
+        .flat_map(|table| table.packets.iter())
+}
+
+type Entry = (DirectionEnum, PacketKind, i32);
+
+/// Packet ids of a range of protocol versions.
+struct Table {
+    versions: RangeInclusive<ProtocolVersion>,
+    packets: &'static [Entry],
+}
+
+/// Upper bound of the tables that still apply to the newest versions
+const UNBOUNDED: ProtocolVersion = ProtocolVersion(i32::MAX);
+
+use DirectionEnum::{C2S, S2C};
+use PacketKind as P;
+
+/// Supporting a new version means adding (or extending) tables here
+static TABLES: &[Table] = &[
+    // Handshake, status and the base of login never changed.
+    // Starts at 0, so the handshake is known before the version is
+    Table {
+        versions: ProtocolVersion(0)..=UNBOUNDED,
+        packets: &[
+            (C2S, P::Handshake, 0x00),
+            (C2S, P::StatusRequest, 0x00),
+            (C2S, P::PingRequest, 0x01),
+            (S2C, P::StatusResponse, 0x00),
+            (S2C, P::PongResponse, 0x01),
+            (C2S, P::LoginStart, 0x00),
+            (C2S, P::EncryptionResponse, 0x01),
+            (S2C, P::LoginDisconnect, 0x00),
+            (S2C, P::EncryptionRequest, 0x01),
+            (S2C, P::LoginSuccess, 0x02),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_8..=UNBOUNDED,
+        packets: &[
+            (S2C, P::SetCompression, 0x03),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_13..=UNBOUNDED,
+        packets: &[
+            (S2C, P::LoginPluginRequest, 0x04),
+            (C2S, P::LoginPluginResponse, 0x02),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_20_2..=UNBOUNDED,
+        packets: &[
+            (C2S, P::LoginAcknowledged, 0x03),
+        ],
+    },
+
+    // Play. Only Disconnect is known before 1.19, so the proxy can kick players of these versions
+    Table {
+        versions: ProtocolVersion::V1_7_2..=ProtocolVersion::V1_8,
+        packets: &[
+            (S2C, P::Disconnect, 0x40),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_9..=ProtocolVersion::V1_12_2,
+        packets: &[
+            (S2C, P::Disconnect, 0x1a),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_13..=ProtocolVersion::V1_13_2,
+        packets: &[
+            (S2C, P::Disconnect, 0x1b),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_14..=ProtocolVersion::V1_14_4,
+        packets: &[
+            (S2C, P::Disconnect, 0x1a),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_15..=ProtocolVersion::V1_15_2,
+        packets: &[
+            (S2C, P::Disconnect, 0x1b),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_16..=ProtocolVersion::V1_16_1,
+        packets: &[
+            (S2C, P::Disconnect, 0x1a),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_16_2..=ProtocolVersion::V1_16_4,
+        packets: &[
+            (S2C, P::Disconnect, 0x19),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_17..=ProtocolVersion::V1_18_2,
+        packets: &[
+            (S2C, P::Disconnect, 0x1a),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_19..=ProtocolVersion::V1_19,
+        packets: &[
+            (S2C, P::JoinGame, 0x23),
+            (S2C, P::Respawn, 0x3b),
+            (S2C, P::KeepAlive, 0x1e),
+            (C2S, P::KeepAlive, 0x11),
+            (S2C, P::Disconnect, 0x17),
+            (S2C, P::SystemChat, 0x5f),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_19_1..=ProtocolVersion::V1_19_1,
+        packets: &[
+            (S2C, P::JoinGame, 0x25),
+            (S2C, P::Respawn, 0x3e),
+            (S2C, P::KeepAlive, 0x20),
+            (C2S, P::KeepAlive, 0x12),
+            (S2C, P::Disconnect, 0x19),
+            (S2C, P::SystemChat, 0x62),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_19_3..=ProtocolVersion::V1_19_3,
+        packets: &[
+            (S2C, P::JoinGame, 0x24),
+            (S2C, P::Respawn, 0x3d),
+            (S2C, P::KeepAlive, 0x1f),
+            (C2S, P::KeepAlive, 0x11),
+            (S2C, P::Disconnect, 0x17),
+            (S2C, P::SystemChat, 0x60),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_19_4..=ProtocolVersion::V1_20,
+        packets: &[
+            (S2C, P::JoinGame, 0x28),
+            (S2C, P::Respawn, 0x41),
+            (S2C, P::KeepAlive, 0x23),
+            (C2S, P::KeepAlive, 0x12),
+            (S2C, P::Disconnect, 0x1a),
+            (S2C, P::SystemChat, 0x64),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_20_2..=ProtocolVersion::V1_20_2,
+        packets: &[
+            (S2C, P::JoinGame, 0x29),
+            (S2C, P::Respawn, 0x43),
+            (S2C, P::KeepAlive, 0x24),
+            (C2S, P::KeepAlive, 0x14),
+            (S2C, P::Disconnect, 0x1b),
+            (S2C, P::SystemChat, 0x67),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_20_3..=ProtocolVersion::V1_20_3,
+        packets: &[
+            (S2C, P::JoinGame, 0x29),
+            (S2C, P::Respawn, 0x45),
+            (S2C, P::KeepAlive, 0x24),
+            (C2S, P::KeepAlive, 0x15),
+            (S2C, P::Disconnect, 0x1b),
+            (S2C, P::SystemChat, 0x69),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_20_5..=ProtocolVersion::V1_21,
+        packets: &[
+            (S2C, P::JoinGame, 0x2b),
+            (S2C, P::Respawn, 0x47),
+            (S2C, P::KeepAlive, 0x26),
+            (C2S, P::KeepAlive, 0x18),
+            (S2C, P::Disconnect, 0x1d),
+            (S2C, P::SystemChat, 0x6c),
+        ],
+    },
+
+    // Moving in and out of the configuration state
+    Table {
+        versions: ProtocolVersion::V1_20_2..=ProtocolVersion::V1_20_2,
+        packets: &[
+            (S2C, P::StartConfiguration, 0x65),
+            (S2C, P::ConfigurationDisconnect, 0x01),
+            (C2S, P::AcknowledgeConfiguration, 0x0b),
+            (S2C, P::FinishConfiguration, 0x02),
+            (C2S, P::AcknowledgeFinishConfiguration, 0x02),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_20_3..=ProtocolVersion::V1_20_3,
+        packets: &[
+            (S2C, P::StartConfiguration, 0x67),
+            (S2C, P::ConfigurationDisconnect, 0x01),
+            (C2S, P::AcknowledgeConfiguration, 0x0b),
+            (S2C, P::FinishConfiguration, 0x02),
+            (C2S, P::AcknowledgeFinishConfiguration, 0x02),
+        ],
+    },
+    Table {
+        versions: ProtocolVersion::V1_20_5..=ProtocolVersion::V1_21,
+        packets: &[
+            (S2C, P::StartConfiguration, 0x69),
+            (S2C, P::ConfigurationDisconnect, 0x02),
+            (C2S, P::AcknowledgeConfiguration, 0x0c),
+            (S2C, P::FinishConfiguration, 0x03),
+            (C2S, P::AcknowledgeFinishConfiguration, 0x03),
+        ],
+    },
+];
@@ -1,12 +1,33 @@
-use crate::{utils::{DataReadExt, DataWriteExt}, State, uuid::UUID3};
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
 
-use super::{ReadExactPacket, WriteExactPacket};
+use crate::{utils::{DataReadExt, DataWriteExt}, State, uuid::UUID3, version::ProtocolVersion, chat::ChatComponent, nbt::{read_raw_nbt, read_nbt_json, write_nbt_json}};
 
-#[derive(Debug, Clone)]
+use super::{ReadExactPacket, WriteExactPacket, Packet, field::PacketField, protocol_version};
+
+#[derive(Debug, Clone, Packet)]
 pub struct LoginSuccess {
-    pub uuid: UUID3,
+    #[since(ProtocolVersion::V1_16)]
+    pub uuid: Option<UUID3>,
+    /// The UUID with hyphens, before 1.16
+    #[until(ProtocolVersion::V1_15_2)]
+    pub uuid_string: Option<String>,
     pub username: String,
+    #[since(ProtocolVersion::V1_19)]
+    #[length_prefixed]
     pub properties: Option<Vec<Property>>,
+    /// Whether the client should disconnect on packets it can't read
+    #[since(ProtocolVersion::V1_20_5)]
+    #[until(ProtocolVersion::V1_21)]
+    pub strict_error_handling: Option<bool>,
+}
+
+impl LoginSuccess {
+    /// Set the UUID the way every protocol version sends it.
+    pub fn set_uuid(&mut self, uuid: UUID3) {
+        self.uuid = Some(uuid);
+        self.uuid_string = Some(uuid.to_string());
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -17,99 +38,568 @@ pub struct Property {
 }
 
 #[async_trait::async_trait]
-impl ReadExactPacket for LoginSuccess {
+impl PacketField for Property {
+    async fn read_field(reader: &mut (impl DataReadExt + Send)) -> anyhow::Result<Self> {
+        let name = reader.read_string().await?;
+        let value = reader.read_string().await?;
+        let signature = if reader.read_bool().await? {
+            Some(reader.read_string().await?)
+        } else {
+            None
+        };
+
+        Ok(Self { name, value, signature })
+    }
+
+    async fn write_field(&self, writer: &mut (impl DataWriteExt + Send)) -> anyhow::Result<()> {
+        writer.write_string(&self.name).await?;
+        writer.write_string(&self.value).await?;
+        writer.write_bool(self.signature.is_some()).await?;
+        if let Some(signature) = &self.signature {
+            writer.write_string(signature).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Set Compression packet, enables compression for all the following packets
+#[derive(Debug, Clone, Packet)]
+pub struct SetCompression {
+    /// Packets of this size or bigger get compressed. Negative value disables compression.
+    #[varint]
+    pub threshold: i32,
+}
+
+/// Encryption Request packet, starts the online-mode encryption handshake
+#[derive(Debug, Clone, Packet)]
+pub struct EncryptionRequest {
+    /// Always empty since 1.7
+    pub server_id: String,
+    /// DER encoded RSA public key
+    #[length_prefixed]
+    pub public_key: Vec<u8>,
+    #[length_prefixed]
+    pub verify_token: Vec<u8>,
+    /// Whether the client should authenticate with Mojang
+    #[since(ProtocolVersion::V1_20_5)]
+    pub should_authenticate: Option<bool>,
+}
+
+/// Status Response packet, the answer to the server list ping
+#[derive(Debug, Clone, Packet)]
+pub struct StatusResponse {
+    #[json]
+    pub status: ServerStatus,
+}
+
+/// JSON model of the status response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub version: StatusVersion,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub players: Option<StatusPlayers>,
+    #[serde(default)]
+    pub description: ChatComponent,
+    /// PNG image encoded as `data:image/png;base64,...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
+    /// Fields motion doesn't care about (e.g. `enforcesSecureChat` or Forge mod info)
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusVersion {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusPlayers {
+    pub max: i32,
+    pub online: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample: Vec<StatusPlayer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusPlayer {
+    pub name: String,
+    pub id: String,
+}
+
+/// Pong Response packet, echoes the payload of the ping request
+#[derive(Debug, Clone, Packet)]
+pub struct PongResponse {
+    pub payload: i64,
+}
+
+/// Disconnect packet of the login state
+#[derive(Debug, Clone, Packet)]
+pub struct LoginDisconnect {
+    #[json]
+    pub reason: ChatComponent,
+}
+
+/// Login Plugin Request packet, lets servers talk to mods (or proxies) before the player joins
+#[derive(Debug, Clone, Packet)]
+pub struct LoginPluginRequest {
+    #[varint]
+    pub message_id: i32,
+    pub channel: String,
+    #[rest]
+    pub data: Vec<u8>,
+}
+
+/// Finish Configuration packet, the server is done configuring the client (1.20.2+)
+#[derive(Debug, Clone, Packet)]
+pub struct FinishConfiguration;
+
+/// Start Configuration packet, sends a playing client back to the configuration state (1.20.2+)
+#[derive(Debug, Clone, Packet)]
+pub struct StartConfiguration;
+
+/// Where the player died last, shown by recovery compasses
+#[derive(Debug, Clone)]
+pub struct DeathLocation {
+    pub dimension_name: String,
+    /// Packed block position
+    pub position: i64,
+}
+
+async fn read_death_location(
+    reader: &mut (impl DataReadExt + std::marker::Send)
+) -> anyhow::Result<Option<DeathLocation>> {
+    if !reader.read_bool().await? {
+        return Ok(None);
+    }
+
+    let dimension_name = reader.read_string().await?;
+    let position = reader.read_i64().await?;
+
+    Ok(Some(DeathLocation { dimension_name, position }))
+}
+
+async fn write_death_location(
+    writer: &mut (impl DataWriteExt + std::marker::Send),
+    death_location: &Option<DeathLocation>
+) -> anyhow::Result<()> {
+    writer.write_bool(death_location.is_some()).await?;
+
+    if let Some(death_location) = death_location {
+        writer.write_string(&death_location.dimension_name).await?;
+        writer.write_i64(death_location.position).await?;
+    }
+
+    Ok(())
+}
+
+/// Dimension type of a world, a registry id since 1.20.5
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DimensionType {
+    Name(String),
+    Id(i32),
+}
+
+async fn read_dimension_type(
+    reader: &mut (impl DataReadExt + std::marker::Send),
+    protocol_version: ProtocolVersion
+) -> anyhow::Result<DimensionType> {
+    if protocol_version >= ProtocolVersion::V1_20_5 {
+        Ok(DimensionType::Id(reader.read_varint().await?))
+    } else {
+        Ok(DimensionType::Name(reader.read_string().await?))
+    }
+}
+
+async fn write_dimension_type(
+    writer: &mut (impl DataWriteExt + std::marker::Send),
+    dimension_type: &DimensionType,
+    protocol_version: ProtocolVersion
+) -> anyhow::Result<()> {
+    match (dimension_type, protocol_version >= ProtocolVersion::V1_20_5) {
+        (DimensionType::Id(id), true) => writer.write_varint(*id).await,
+        (DimensionType::Name(name), false) => writer.write_string(name).await,
+        (dimension_type, _) => Err(anyhow::anyhow!("{:?} can't be sent to protocol version {}", dimension_type, protocol_version)),
+    }
+}
+
+/// Join Game (Login (play)) packet, the first packet of the play state
+#[derive(Debug, Clone)]
+pub struct JoinGame {
+    pub entity_id: i32,
+    pub is_hardcore: bool,
+    pub game_mode: u8,
+    pub previous_game_mode: i8,
+    pub dimension_names: Vec<String>,
+    /// Raw NBT, motion doesn't need to look inside.
+    /// Until 1.20.2, the registries are sent in the configuration state since
+    pub registry_codec: Option<Vec<u8>>,
+    pub dimension_type: DimensionType,
+    pub dimension_name: String,
+    pub hashed_seed: i64,
+    pub max_players: i32,
+    pub view_distance: i32,
+    pub simulation_distance: i32,
+    pub reduced_debug_info: bool,
+    pub enable_respawn_screen: bool,
+    /// Since 1.20.2
+    pub do_limited_crafting: Option<bool>,
+    pub is_debug: bool,
+    pub is_flat: bool,
+    pub death_location: Option<DeathLocation>,
+    /// Since 1.20
+    pub portal_cooldown: Option<i32>,
+    /// Since 1.20.5
+    pub enforces_secure_chat: Option<bool>,
+}
+
+#[async_trait::async_trait]
+impl ReadExactPacket for JoinGame {
     async fn read_packet(
         mut reader: impl DataReadExt + std::marker::Send, 
         state: &State
     ) -> anyhow::Result<Self> where Self: Sized {
-        if state.handshake.is_none() {
-            return Err(anyhow::anyhow!("Handshake packet not received"));
+        let protocol_version = protocol_version(state)?;
+        // The fields shared with Respawn moved to the end in 1.20.2
+        let spawn_info_last = protocol_version >= ProtocolVersion::V1_20_2;
+
+        let entity_id = reader.read_i32().await?;
+        let is_hardcore = reader.read_bool().await?;
+        let (mut game_mode, mut previous_game_mode) = (0, 0);
+        if !spawn_info_last {
+            game_mode = reader.read_u8().await?;
+            previous_game_mode = reader.read_i8().await?;
         }
-        let handshake = state.handshake.as_ref().unwrap();
 
-        let uuid = if handshake.protocol_version >= 735 {
-            reader.read_uuid().await.unwrap()
+        let mut dimension_names = vec![];
+        for _ in 0..reader.read_varint().await? {
+            dimension_names.push(reader.read_string().await?);
+        }
+
+        let mut registry_codec = None;
+        let mut dimension_type = DimensionType::Name(String::new());
+        let mut dimension_name = String::new();
+        let mut hashed_seed = 0;
+        if !spawn_info_last {
+            registry_codec = Some(read_raw_nbt(&mut reader).await?);
+            dimension_type = read_dimension_type(&mut reader, protocol_version).await?;
+            dimension_name = reader.read_string().await?;
+            hashed_seed = reader.read_i64().await?;
+        }
+
+        let max_players = reader.read_varint().await?;
+        let view_distance = reader.read_varint().await?;
+        let simulation_distance = reader.read_varint().await?;
+        let reduced_debug_info = reader.read_bool().await?;
+        let enable_respawn_screen = reader.read_bool().await?;
+
+        let do_limited_crafting = if spawn_info_last {
+            let do_limited_crafting = reader.read_bool().await?;
+            dimension_type = read_dimension_type(&mut reader, protocol_version).await?;
+            dimension_name = reader.read_string().await?;
+            hashed_seed = reader.read_i64().await?;
+            game_mode = reader.read_u8().await?;
+            previous_game_mode = reader.read_i8().await?;
+
+            Some(do_limited_crafting)
         } else {
-            UUID3::try_from(reader.read_string().await?)?
+            None
         };
-        let username = reader.read_string().await.unwrap();
 
-        let mut properties: Option<Vec<Property>> = None;
+        let is_debug = reader.read_bool().await?;
+        let is_flat = reader.read_bool().await?;
+        let death_location = read_death_location(&mut reader).await?;
+
+        let portal_cooldown = if protocol_version >= ProtocolVersion::V1_20 {
+            Some(reader.read_varint().await?)
+        } else {
+            None
+        };
+        let enforces_secure_chat = if protocol_version >= ProtocolVersion::V1_20_5 {
+            Some(reader.read_bool().await?)
+        } else {
+            None
+        };
 
-        if handshake.protocol_version >= 759 {
-            properties = Some(vec![]);
+        Ok(Self {
+            entity_id, is_hardcore, game_mode, previous_game_mode, dimension_names,
+            registry_codec, dimension_type, dimension_name, hashed_seed, max_players,
+            view_distance, simulation_distance, reduced_debug_info, enable_respawn_screen,
+            do_limited_crafting, is_debug, is_flat, death_location, portal_cooldown, enforces_secure_chat,
+        })
+    }
+}
 
-            let n = reader.read_varint().await?;
-            for _ in 0..n {
-                let name = reader.read_string().await?;
-                let value = reader.read_string().await?;
+#[async_trait::async_trait]
+impl WriteExactPacket for JoinGame {
+    async fn write_packet(
+        &self, 
+        mut writer: impl DataWriteExt + std::marker::Send, 
+        state: &State
+    ) -> anyhow::Result<()> {
+        let protocol_version = protocol_version(state)?;
+        let spawn_info_last = protocol_version >= ProtocolVersion::V1_20_2;
 
-                let signature = if reader.read_bool().await.unwrap() {
-                    Some(reader.read_string().await?)
-                } else {
-                    None
-                };
+        writer.write_i32(self.entity_id).await?;
+        writer.write_bool(self.is_hardcore).await?;
+        if !spawn_info_last {
+            writer.write_u8(self.game_mode).await?;
+            writer.write_i8(self.previous_game_mode).await?;
+        }
 
-                properties.as_mut().unwrap().push(Property {
-                    name, value, signature,
-                });
-            }
+        writer.write_varint(self.dimension_names.len() as i32).await?;
+        for dimension_name in &self.dimension_names {
+            writer.write_string(dimension_name).await?;
+        }
+
+        if !spawn_info_last {
+            let registry_codec = self.registry_codec.as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Registry codec is None, but protocol version is below 1.20.2"))?;
+            writer.write_all(registry_codec).await?;
+            write_dimension_type(&mut writer, &self.dimension_type, protocol_version).await?;
+            writer.write_string(&self.dimension_name).await?;
+            writer.write_i64(self.hashed_seed).await?;
+        }
+
+        writer.write_varint(self.max_players).await?;
+        writer.write_varint(self.view_distance).await?;
+        writer.write_varint(self.simulation_distance).await?;
+        writer.write_bool(self.reduced_debug_info).await?;
+        writer.write_bool(self.enable_respawn_screen).await?;
+
+        if spawn_info_last {
+            writer.write_bool(self.do_limited_crafting.unwrap_or_default()).await?;
+            write_dimension_type(&mut writer, &self.dimension_type, protocol_version).await?;
+            writer.write_string(&self.dimension_name).await?;
+            writer.write_i64(self.hashed_seed).await?;
+            writer.write_u8(self.game_mode).await?;
+            writer.write_i8(self.previous_game_mode).await?;
+        }
+
+        writer.write_bool(self.is_debug).await?;
+        writer.write_bool(self.is_flat).await?;
+        write_death_location(&mut writer, &self.death_location).await?;
+
+        if protocol_version >= ProtocolVersion::V1_20 {
+            writer.write_varint(self.portal_cooldown.unwrap_or_default()).await?;
+        }
+        if protocol_version >= ProtocolVersion::V1_20_5 {
+            writer.write_bool(self.enforces_secure_chat.unwrap_or_default()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Respawn packet, moves the player to another dimension
+#[derive(Debug, Clone)]
+pub struct Respawn {
+    pub dimension_type: DimensionType,
+    pub dimension_name: String,
+    pub hashed_seed: i64,
+    pub game_mode: u8,
+    pub previous_game_mode: i8,
+    pub is_debug: bool,
+    pub is_flat: bool,
+    /// Bit mask of the player data to keep (attributes, metadata)
+    pub data_kept: u8,
+    pub death_location: Option<DeathLocation>,
+    /// Since 1.20
+    pub portal_cooldown: Option<i32>,
+}
+
+impl Respawn {
+    /// Respawn into the dimension the player joined.
+    pub fn from_join_game(join_game: &JoinGame) -> Self {
+        Self {
+            dimension_type: join_game.dimension_type.clone(),
+            dimension_name: join_game.dimension_name.clone(),
+            hashed_seed: join_game.hashed_seed,
+            game_mode: join_game.game_mode,
+            previous_game_mode: join_game.previous_game_mode,
+            is_debug: join_game.is_debug,
+            is_flat: join_game.is_flat,
+            data_kept: 0,
+            death_location: join_game.death_location.clone(),
+            portal_cooldown: join_game.portal_cooldown,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ReadExactPacket for Respawn {
+    async fn read_packet(
+        mut reader: impl DataReadExt + std::marker::Send, 
+        state: &State
+    ) -> anyhow::Result<Self> where Self: Sized {
+        let protocol_version = protocol_version(state)?;
+        // Sent last since 1.20.2
+        let data_kept_last = protocol_version >= ProtocolVersion::V1_20_2;
+
+        let dimension_type = read_dimension_type(&mut reader, protocol_version).await?;
+        let dimension_name = reader.read_string().await?;
+        let hashed_seed = reader.read_i64().await?;
+        let game_mode = reader.read_u8().await?;
+        let previous_game_mode = reader.read_i8().await?;
+        let is_debug = reader.read_bool().await?;
+        let is_flat = reader.read_bool().await?;
+        let mut data_kept = if data_kept_last { 0 } else { reader.read_u8().await? };
+        let death_location = read_death_location(&mut reader).await?;
+
+        let portal_cooldown = if protocol_version >= ProtocolVersion::V1_20 {
+            Some(reader.read_varint().await?)
+        } else {
+            None
+        };
+        if data_kept_last {
+            data_kept = reader.read_u8().await?;
         }
 
         Ok(Self {
-            uuid,
-            username,
-            properties,
+            dimension_type, dimension_name, hashed_seed, game_mode, previous_game_mode,
+            is_debug, is_flat, data_kept, death_location, portal_cooldown,
         })
     }
 }
 
 #[async_trait::async_trait]
-impl WriteExactPacket for LoginSuccess {
+impl WriteExactPacket for Respawn {
     async fn write_packet(
         &self, 
         mut writer: impl DataWriteExt + std::marker::Send, 
         state: &State
     ) -> anyhow::Result<()> {
-        if state.handshake.is_none() {
-            return Err(anyhow::anyhow!("Handshake packet not received"));
-        }
-        let handshake = state.handshake.as_ref().unwrap();
+        let protocol_version = protocol_version(state)?;
+        let data_kept_last = protocol_version >= ProtocolVersion::V1_20_2;
 
-        let mut data = vec![];
+        write_dimension_type(&mut writer, &self.dimension_type, protocol_version).await?;
+        writer.write_string(&self.dimension_name).await?;
+        writer.write_i64(self.hashed_seed).await?;
+        writer.write_u8(self.game_mode).await?;
+        writer.write_i8(self.previous_game_mode).await?;
+        writer.write_bool(self.is_debug).await?;
+        writer.write_bool(self.is_flat).await?;
+        if !data_kept_last {
+            writer.write_u8(self.data_kept).await?;
+        }
+        write_death_location(&mut writer, &self.death_location).await?;
 
-        if handshake.protocol_version >= 735 {
-            data.write_uuid(self.uuid).await?;
-        } else {
-            data.write_string(&self.uuid.to_string()).await?;
+        if protocol_version >= ProtocolVersion::V1_20 {
+            writer.write_varint(self.portal_cooldown.unwrap_or_default()).await?;
+        }
+        if data_kept_last {
+            writer.write_u8(self.data_kept).await?;
         }
 
-        data.write_string(&self.username).await?;
+        Ok(())
+    }
+}
 
-        if handshake.protocol_version >= 759 {
-            if self.properties.is_none() {
-                return Err(anyhow::anyhow!("Properties is None, but protocol version >= 759"));
-            }
-            let properties = self.properties.as_ref().unwrap();
+/// Keep Alive packet of the play state, the client has to answer with the same id
+#[derive(Debug, Clone, Packet)]
+pub struct KeepAlive {
+    pub id: i64,
+}
 
-            data.write_varint(properties.len() as i32).await?;
+/// Disconnect packet of the play and configuration states
+#[derive(Debug, Clone)]
+pub struct Disconnect {
+    pub reason: ChatComponent,
+}
 
-            for property in properties {
-                data.write_string(&property.name).await?;
-                data.write_string(&property.value).await?;
+#[async_trait::async_trait]
+impl ReadExactPacket for Disconnect {
+    async fn read_packet(
+        mut reader: impl DataReadExt + std::marker::Send, 
+        state: &State
+    ) -> anyhow::Result<Self> where Self: Sized {
+        let reason = read_text_component(&mut reader, state).await?;
+
+        Ok(Self { reason })
+    }
+}
+
+#[async_trait::async_trait]
+impl WriteExactPacket for Disconnect {
+    async fn write_packet(
+        &self, 
+        mut writer: impl DataWriteExt + std::marker::Send, 
+        state: &State
+    ) -> anyhow::Result<()> {
+        write_text_component(&mut writer, &self.reason, state).await
+    }
+}
+
+/// Text components outside of the login state are NBT since 1.20.3, JSON before
+async fn read_text_component(
+    reader: &mut (impl DataReadExt + std::marker::Send),
+    state: &State
+) -> anyhow::Result<ChatComponent> {
+    if protocol_version(state)? >= ProtocolVersion::V1_20_3 {
+        Ok(serde_json::from_value(read_nbt_json(reader).await?)?)
+    } else {
+        Ok(serde_json::from_str(&reader.read_string().await?)?)
+    }
+}
 
-                if let Some(signature) = &property.signature {
-                    data.write_bool(true).await?;
-                    data.write_string(signature).await?;
-                } else {
-                    data.write_bool(false).await?;
-                }
-            }
+async fn write_text_component(
+    writer: &mut (impl DataWriteExt + std::marker::Send),
+    component: &ChatComponent,
+    state: &State
+) -> anyhow::Result<()> {
+    if protocol_version(state)? >= ProtocolVersion::V1_20_3 {
+        let mut nbt = vec![];
+        write_nbt_json(&serde_json::to_value(component)?, &mut nbt)?;
+        writer.write_all(&nbt).await?;
+    } else {
+        writer.write_string(&serde_json::to_string(component)?).await?;
+    }
+
+    Ok(())
+}
+
+/// System chat message, only ever sent by the proxy itself
+#[derive(Debug, Clone)]
+pub struct SystemChat {
+    pub content: ChatComponent,
+    /// Shown above the hotbar instead of in the chat
+    pub overlay: bool,
+}
+
+#[async_trait::async_trait]
+impl ReadExactPacket for SystemChat {
+    async fn read_packet(
+        mut reader: impl DataReadExt + std::marker::Send, 
+        state: &State
+    ) -> anyhow::Result<Self> where Self: Sized {
+        let content = read_text_component(&mut reader, state).await?;
+        // 1.19 has a chat type instead, 2 being the game info above the hotbar
+        let overlay = if protocol_version(state)? == ProtocolVersion::V1_19 {
+            reader.read_varint().await? == 2
+        } else {
+            reader.read_bool().await?
+        };
+
+        Ok(Self { content, overlay })
+    }
+}
+
+#[async_trait::async_trait]
+impl WriteExactPacket for SystemChat {
+    async fn write_packet(
+        &self, 
+        mut writer: impl DataWriteExt + std::marker::Send, 
+        state: &State
+    ) -> anyhow::Result<()> {
+        write_text_component(&mut writer, &self.content, state).await?;
+        if protocol_version(state)? == ProtocolVersion::V1_19 {
+            writer.write_varint(if self.overlay { 2 } else { 1 }).await?;
+        } else {
+            writer.write_bool(self.overlay).await?;
         }
-        writer.write_varint(data.len() as i32 + 1).await?;
-        writer.write_u8(0x02).await?; // TODO: some of protocol versions user another packet id
-        writer.write_all(&data).await?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
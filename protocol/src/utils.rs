@@ -1,6 +1,34 @@
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::uuid::UUID3;
+use crate::{uuid::UUID3, codec::decode_varint, error::{ProtocolError, PacketContext}};
+
+/// Longest string the protocol allows, in characters
+pub const MAX_STRING_LENGTH: usize = 32767;
+
+/// Reads a VarInt from the beginning of a slice, returning the value
+/// and the amount of bytes it took. Like [`decode_varint`], for data that has to be complete.
+pub fn read_varint_slice(data: &[u8]) -> anyhow::Result<(i32, usize)> {
+    decode_varint(data)?
+        .ok_or_else(|| ProtocolError::UnexpectedEof { context: PacketContext::default() }.into())
+}
+
+/// Appends a VarInt to a buffer without going through the async writer.
+pub fn write_varint_vec(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+
+    loop {
+        let mut temp = (value & 0b01111111) as u8;
+        value >>= 7;
+        if value != 0 {
+            temp |= 0b10000000;
+        }
+        buf.push(temp);
+
+        if value == 0 {
+            break;
+        }
+    }
+}
 
 #[async_trait::async_trait]
 pub trait DataReadExt: AsyncReadExt + Unpin {
@@ -10,14 +38,15 @@ pub trait DataReadExt: AsyncReadExt + Unpin {
         let mut data = vec![];
 
         loop {
+            if num_read >= 5 {
+                return Err(ProtocolError::VarIntTooLong { context: PacketContext::default() }.into());
+            }
+
             let read = self.read_u8().await?;
             let value = (read & 0x7f) as i32;
             result |= value << (7 * num_read);
 
             num_read += 1;
-            if num_read > 5 {
-                return Err(anyhow::anyhow!("VarInt is too big"));
-            }
 
             data.push(read);
 
@@ -34,14 +63,15 @@ pub trait DataReadExt: AsyncReadExt + Unpin {
         let mut result = 0;
 
         loop {
+            if num_read >= 5 {
+                return Err(ProtocolError::VarIntTooLong { context: PacketContext::default() }.into());
+            }
+
             let read = self.read_u8().await?;
             let value = (read & 0x7f) as i32;
             result |= value << (7 * num_read);
 
             num_read += 1;
-            if num_read > 5 {
-                return Err(anyhow::anyhow!("VarInt is too big"));
-            }
 
             if read & 0x80 == 0 {
                 break;
@@ -58,11 +88,48 @@ pub trait DataReadExt: AsyncReadExt + Unpin {
     }
 
     async fn read_string(&mut self) -> anyhow::Result<String> {
+        self.read_string_bounded(MAX_STRING_LENGTH).await
+    }
+
+    /// Read a string of at most `max_length` characters,
+    /// longer ones are rejected by their length prefix before they are read.
+    async fn read_string_bounded(&mut self, max_length: usize) -> anyhow::Result<String> {
+        let length = self.read_length().await?;
+        // A character takes up to 3 bytes
+        if length > max_length * 3 {
+            return Err(ProtocolError::StringTooLong { max: max_length, context: PacketContext::default() }.into());
+        }
+
+        let string = String::from_utf8(self.read_bytes(length).await?)
+            .map_err(|_| ProtocolError::InvalidUtf8 { context: PacketContext::default() })?;
+        if string.encode_utf16().count() > max_length {
+            return Err(ProtocolError::StringTooLong { max: max_length, context: PacketContext::default() }.into());
+        }
+
+        Ok(string)
+    }
+
+    async fn read_byte_array(&mut self) -> anyhow::Result<Vec<u8>> {
+        let length = self.read_length().await?;
+
+        self.read_bytes(length).await
+    }
+
+    /// VarInt length prefix of a string, array or list
+    async fn read_length(&mut self) -> anyhow::Result<usize> {
         let length = self.read_varint().await?;
-        let mut buf = vec![0; length as usize];
-        self.read_exact(&mut buf).await?;
 
-        Ok(String::from_utf8(buf)?)
+        Ok(usize::try_from(length).map_err(|_| ProtocolError::NegativeLength { length, context: PacketContext::default() })?)
+    }
+
+    /// Read exactly `length` bytes. The length isn't trusted for allocating, the data has to actually be there.
+    async fn read_bytes(&mut self, length: usize) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![];
+        if self.take(length as u64).read_to_end(&mut buf).await? != length {
+            return Err(ProtocolError::UnexpectedEof { context: PacketContext::default() }.into());
+        }
+
+        Ok(buf)
     }
 
     async fn read_uuid(&mut self) -> anyhow::Result<UUID3> {
@@ -89,7 +156,7 @@ pub trait DataWriteExt: AsyncWriteExt + Unpin {
         
         while value != 0 {
             let mut temp = (value & 0b01111111) as u8;
-            value = (value >> 7) & (i32::max_value() as i32);
+            value = (value >> 7) & i32::MAX;
             if value != 0 {
                 temp |= 0b10000000;
             }
@@ -105,6 +172,13 @@ pub trait DataWriteExt: AsyncWriteExt + Unpin {
         Ok(())
     }
 
+    async fn write_byte_array(&mut self, value: &[u8]) -> anyhow::Result<()> {
+        self.write_varint(value.len() as i32).await?;
+        self.write_all(value).await?;
+
+        Ok(())
+    }
+
     async fn write_uuid(&mut self, value: UUID3) -> anyhow::Result<()> {
         let converted: [u8; 16] = value.into();
         self.write_all(&converted).await?;
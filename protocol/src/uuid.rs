@@ -1,7 +1,5 @@
 use std::fmt::Debug;
 
-use md5::Digest;
-
 #[derive(Copy, Clone, PartialEq, Hash, Eq, Debug)]
 pub struct UUID3 {
     raw: u128,
@@ -19,40 +17,37 @@ impl From<UUID3> for u128 {
     }
 }
 
-impl Into<[u8; 16]> for UUID3 {
-    fn into(self) -> [u8; 16] {
-        self.raw.to_be_bytes()
+impl From<UUID3> for [u8; 16] {
+    fn from(uuid: UUID3) -> Self {
+        uuid.raw.to_be_bytes()
     }
 }
 
 impl TryFrom<String> for UUID3 {
     type Error = anyhow::Error;
 
-    /// I have no clue if this is correct...
+    /// Accepts both hyphenated and Mojang's undashed form.
     fn try_from(data: String) -> anyhow::Result<Self> {
-        if data.len() != 36 {
+        if data.len() != 36 && data.len() != 32 {
             return Err(anyhow::anyhow!("Invalid UUID length: {}", data.len()));
         }
 
-        let data = data.replace("-", "");
+        let data = data.replace('-', "");
+        if data.len() != 32 {
+            return Err(anyhow::anyhow!("Invalid UUID: {}", data));
+        }
         let raw = u128::from_str_radix(&data, 16)?; 
 
         Ok(Self { raw })
     }
 }
 
-impl ToString for UUID3 {
-    fn to_string(&self) -> String {
-        let mut data = [0u8; 16];
-
-        for i in 0..16 {
-            data[i] = ((self.raw >> (i * 8)) & 0xFF) as u8;
-        }
+impl std::fmt::Display for UUID3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = format!("{:032x}", self.raw);
 
-        let dig = Digest(data);
-        let string = format!("{:x}", dig);
-
-        format!(
+        write!(
+            f,
             "{}-{}-{}-{}-{}", 
             &string[0..8], 
             &string[8..12], 
@@ -64,15 +59,41 @@ impl ToString for UUID3 {
 }
 
 impl UUID3 {
+    /// Name based UUID (version 3), same as Java's `UUID.nameUUIDFromBytes`
     pub fn new(data: String) -> Self {
-        let hash = md5::compute(data.as_bytes());
+        let mut hash = md5::compute(data.as_bytes()).0;
 
-        let mut raw = 0u128;
+        hash[6] = (hash[6] & 0x0f) | 0x30;
+        hash[8] = (hash[8] & 0x3f) | 0x80;
 
-        for i in 0..16 {
-            raw |= (hash[i] as u128) << (i * 8);
-        }
+        Self { raw: u128::from_be_bytes(hash) }
+    }
 
-        Self { raw }
+    /// Undashed form, the one Mojang's APIs use
+    pub fn to_simple_string(&self) -> String {
+        format!("{:032x}", self.raw)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_uuid_matches_java() {
+        let uuid = UUID3::new("OfflinePlayer:Notch".to_string());
+
+        assert_eq!(uuid.to_string(), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+        assert_eq!(uuid.to_simple_string(), "b50ad385829d3141a2167e7d7539ba7f");
+    }
+
+    #[test]
+    fn parses_both_forms() {
+        let dashed = UUID3::try_from("069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string()).unwrap();
+        let simple = UUID3::try_from("069a79f444e94726a5befca90e38aaf5".to_string()).unwrap();
+
+        assert_eq!(dashed, simple);
+        assert_eq!(dashed.to_string(), "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+        assert!(UUID3::try_from("069a79f4-44e9-4726-a5be-fca90e38aaf".to_string()).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// Protocol version number sent in the handshake.
+/// Versions are ordered, so features can be gated with comparisons.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProtocolVersion(pub i32);
+
+impl ProtocolVersion {
+    pub const V1_7_2: Self = Self(4);
+    pub const V1_7_6: Self = Self(5);
+    pub const V1_8: Self = Self(47);
+    pub const V1_9: Self = Self(107);
+    pub const V1_9_1: Self = Self(108);
+    pub const V1_9_2: Self = Self(109);
+    pub const V1_9_4: Self = Self(110);
+    pub const V1_10: Self = Self(210);
+    pub const V1_11: Self = Self(315);
+    pub const V1_11_1: Self = Self(316);
+    pub const V1_12: Self = Self(335);
+    pub const V1_12_1: Self = Self(338);
+    pub const V1_12_2: Self = Self(340);
+    pub const V1_13: Self = Self(393);
+    pub const V1_13_1: Self = Self(401);
+    pub const V1_13_2: Self = Self(404);
+    pub const V1_14: Self = Self(477);
+    pub const V1_14_1: Self = Self(480);
+    pub const V1_14_2: Self = Self(485);
+    pub const V1_14_3: Self = Self(490);
+    pub const V1_14_4: Self = Self(498);
+    pub const V1_15: Self = Self(573);
+    pub const V1_15_1: Self = Self(575);
+    pub const V1_15_2: Self = Self(578);
+    pub const V1_16: Self = Self(735);
+    pub const V1_16_1: Self = Self(736);
+    pub const V1_16_2: Self = Self(751);
+    pub const V1_16_3: Self = Self(753);
+    pub const V1_16_4: Self = Self(754);
+    pub const V1_17: Self = Self(755);
+    pub const V1_17_1: Self = Self(756);
+    pub const V1_18: Self = Self(757);
+    pub const V1_18_2: Self = Self(758);
+    pub const V1_19: Self = Self(759);
+    pub const V1_19_1: Self = Self(760);
+    pub const V1_19_3: Self = Self(761);
+    pub const V1_19_4: Self = Self(762);
+    pub const V1_20: Self = Self(763);
+    pub const V1_20_2: Self = Self(764);
+    pub const V1_20_3: Self = Self(765);
+    pub const V1_20_5: Self = Self(766);
+    pub const V1_21: Self = Self(767);
+
+    /// Newest version the proxy knows about
+    pub const LATEST: Self = Self::V1_21;
+}
+
+impl From<i32> for ProtocolVersion {
+    fn from(protocol_version: i32) -> Self {
+        Self(protocol_version)
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}